@@ -1,17 +1,72 @@
 -- ServerSeekerV2 Database Schema
 
 CREATE EXTENSION IF NOT EXISTS "uuid-ossp";
+-- Trigram indexes for searching descriptions with ILIKE
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+-- Server software, detected from the version name, description, mods and plugins
+CREATE TYPE software AS ENUM (
+    'Java',
+    'Neoforge',
+    'Lexforge',
+    'Paper',
+    'Spigot',
+    'Bukkit',
+    'Purpur',
+    'Folia',
+    'Pufferfish',
+    'Velocity',
+    'Leaves',
+    'Waterfall',
+    'Bungeecord',
+    'Thermos',
+    'Fabric',
+    'Quilt',
+    'Sponge',
+    'Mohist',
+    'Magma',
+    'Arclight',
+    'Catserver',
+    'Ketting',
+    'Geyser',
+    'Limbo',
+    'Tcpshield'
+);
+
+-- Why a server is suspected to be a honeypot or a fake server
+CREATE TYPE honeypot_reason AS ENUM (
+    'impossible_player_count',
+    'fake_player_sample',
+    'same_response_on_many_ports',
+    'same_response_across_subnet'
+);
+
+-- Whether a server checks players with Mojang, inferred from player UUIDs or a login probe
+CREATE TYPE auth_mode AS ENUM ('online', 'offline', 'mixed');
+
+-- Result of attempting to log in to a server
+CREATE TYPE login_status AS ENUM (
+    'online_mode',
+    'offline_mode',
+    'whitelisted',
+    'banned',
+    'version_mismatch',
+    'modded_client_required',
+    'disconnected',
+    'unknown'
+);
 
 -- Main servers table
 CREATE TABLE IF NOT EXISTS servers (
     address INET NOT NULL,
     port INTEGER NOT NULL,
-    software TEXT,
+    software software,
     version TEXT,
     protocol INTEGER,
-    icon TEXT,
+    icon_hash TEXT,
     description_raw JSONB,
     description_formatted TEXT,
+    description_plain TEXT,
     prevents_chat_reports BOOLEAN,
     enforces_secure_chat BOOLEAN,
     first_seen INTEGER NOT NULL,
@@ -20,6 +75,83 @@ CREATE TABLE IF NOT EXISTS servers (
     max_players INTEGER,
     country TEXT,
     asn TEXT,
+    latency_ms INTEGER,
+    login_status login_status,
+    disconnect_reason TEXT,
+    mods_truncated BOOLEAN,
+    suspected_honeypot honeypot_reason,
+    sample_text TEXT,
+    hides_players BOOLEAN,
+    auth_mode auth_mode,
+    consecutive_failures INTEGER NOT NULL DEFAULT 0,
+    -- Share of recent pings that failed, older pings count for less and less
+    failure_rate DOUBLE PRECISION NOT NULL DEFAULT 0,
+    last_failure_reason TEXT,
+    is_online BOOLEAN NOT NULL DEFAULT TRUE,
+    archived_at INTEGER,
+    next_scan INTEGER,
+    PRIMARY KEY (address, port)
+);
+
+-- Unique favicons, servers reference these by hash
+CREATE TABLE IF NOT EXISTS favicons (
+    hash TEXT PRIMARY KEY,
+    png BYTEA NOT NULL,
+    first_seen INTEGER NOT NULL
+);
+
+-- Every time a player from [player_tracking] in the config is seen on a server
+-- Players seen in the full query player list only have a name, their UUID is left empty
+CREATE TABLE IF NOT EXISTS player_sightings (
+    address INET NOT NULL,
+    port INTEGER NOT NULL,
+    uuid UUID,
+    name TEXT NOT NULL,
+    seen_at INTEGER NOT NULL
+);
+
+-- Player counts and versions of a server over time, taken at most every
+-- sample_interval seconds and thinned out as they get older
+CREATE TABLE IF NOT EXISTS server_snapshots (
+    address INET NOT NULL,
+    port INTEGER NOT NULL,
+    taken_at INTEGER NOT NULL,
+    online_players INTEGER,
+    max_players INTEGER,
+    software software,
+    version TEXT,
+    protocol INTEGER,
+    latency_ms INTEGER
+);
+
+-- Every time the version, software, MOTD or favicon of a server changes.
+-- MOTDs are stored as the md5 of description_formatted
+CREATE TABLE IF NOT EXISTS server_changes (
+    address INET NOT NULL,
+    port INTEGER NOT NULL,
+    changed_at INTEGER NOT NULL,
+    field TEXT NOT NULL,
+    old TEXT,
+    new TEXT
+);
+
+-- Bedrock Edition servers, found with a RakNet unconnected ping over UDP
+CREATE TABLE IF NOT EXISTS bedrock_servers (
+    address INET NOT NULL,
+    port INTEGER NOT NULL,
+    edition TEXT,
+    version TEXT,
+    protocol INTEGER,
+    description TEXT,
+    level_name TEXT,
+    gamemode TEXT,
+    server_id TEXT,
+    first_seen INTEGER NOT NULL,
+    last_seen INTEGER NOT NULL,
+    online_players INTEGER,
+    max_players INTEGER,
+    country TEXT,
+    asn TEXT,
     PRIMARY KEY (address, port)
 );
 
@@ -34,6 +166,27 @@ CREATE TABLE IF NOT EXISTS players (
     PRIMARY KEY (address, port, uuid)
 );
 
+-- Full player list from the query protocol, which only reports names
+CREATE TABLE IF NOT EXISTS query_players (
+    address INET NOT NULL,
+    port INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    first_seen INTEGER NOT NULL,
+    last_seen INTEGER NOT NULL,
+    PRIMARY KEY (address, port, name)
+);
+
+-- Plugins table, from the query protocol
+CREATE TABLE IF NOT EXISTS plugins (
+    address INET NOT NULL,
+    port INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    version TEXT,
+    first_seen INTEGER NOT NULL,
+    last_seen INTEGER NOT NULL,
+    PRIMARY KEY (address, port, name)
+);
+
 -- Mods table
 CREATE TABLE IF NOT EXISTS mods (
     address INET NOT NULL,
@@ -51,6 +204,29 @@ CREATE TABLE IF NOT EXISTS countries (
     PRIMARY KEY (network)
 );
 
+-- Networks that are never scanned, e.g. after an abuse complaint. Added with --exclude
+CREATE TABLE IF NOT EXISTS scan_exclusions (
+    network CIDR PRIMARY KEY,
+    reason TEXT,
+    added_at INTEGER NOT NULL
+);
+
+-- Servers that put the opt out marker in their description, never scanned again. Removed with --opt-in
+CREATE TABLE IF NOT EXISTS opt_outs (
+    address INET NOT NULL,
+    port INTEGER NOT NULL,
+    opted_out_at INTEGER NOT NULL,
+    PRIMARY KEY (address, port)
+);
+
+-- The same for Bedrock Edition, a UDP port can belong to someone else than the TCP port with the same number
+CREATE TABLE IF NOT EXISTS bedrock_opt_outs (
+    address INET NOT NULL,
+    port INTEGER NOT NULL,
+    opted_out_at INTEGER NOT NULL,
+    PRIMARY KEY (address, port)
+);
+
 -- Server visits table with enhanced status tracking
 CREATE TYPE visit_status AS ENUM ('visited', 'skipped', 'whitelisted');
 
@@ -65,12 +241,105 @@ CREATE TABLE IF NOT EXISTS server_visits (
     FOREIGN KEY (address, port) REFERENCES servers(address, port) ON DELETE CASCADE
 );
 
+-- Software added after the initial schema, for upgrading existing databases
+ALTER TYPE software ADD VALUE IF NOT EXISTS 'Fabric';
+ALTER TYPE software ADD VALUE IF NOT EXISTS 'Quilt';
+ALTER TYPE software ADD VALUE IF NOT EXISTS 'Sponge';
+ALTER TYPE software ADD VALUE IF NOT EXISTS 'Mohist';
+ALTER TYPE software ADD VALUE IF NOT EXISTS 'Magma';
+ALTER TYPE software ADD VALUE IF NOT EXISTS 'Arclight';
+ALTER TYPE software ADD VALUE IF NOT EXISTS 'Catserver';
+ALTER TYPE software ADD VALUE IF NOT EXISTS 'Ketting';
+ALTER TYPE software ADD VALUE IF NOT EXISTS 'Geyser';
+ALTER TYPE software ADD VALUE IF NOT EXISTS 'Limbo';
+ALTER TYPE software ADD VALUE IF NOT EXISTS 'Tcpshield';
+
+-- Software used to be stored as text, every value in it is one of the enum's
+ALTER TABLE servers ALTER COLUMN software TYPE software USING software::software;
+ALTER TABLE server_snapshots ALTER COLUMN software TYPE software USING software::software;
+
+-- Columns added after the initial schema, for upgrading existing databases
+ALTER TABLE servers ADD COLUMN IF NOT EXISTS latency_ms INTEGER;
+ALTER TABLE servers ADD COLUMN IF NOT EXISTS login_status login_status;
+ALTER TABLE servers ADD COLUMN IF NOT EXISTS disconnect_reason TEXT;
+ALTER TABLE servers ADD COLUMN IF NOT EXISTS mods_truncated BOOLEAN;
+ALTER TABLE servers ADD COLUMN IF NOT EXISTS description_plain TEXT;
+ALTER TABLE servers ADD COLUMN IF NOT EXISTS suspected_honeypot honeypot_reason;
+ALTER TABLE servers ADD COLUMN IF NOT EXISTS sample_text TEXT;
+ALTER TABLE servers ADD COLUMN IF NOT EXISTS hides_players BOOLEAN;
+ALTER TABLE servers ADD COLUMN IF NOT EXISTS auth_mode auth_mode;
+ALTER TABLE servers ADD COLUMN IF NOT EXISTS icon_hash TEXT;
+ALTER TABLE servers ADD COLUMN IF NOT EXISTS consecutive_failures INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE servers ADD COLUMN IF NOT EXISTS last_failure_reason TEXT;
+ALTER TABLE servers ADD COLUMN IF NOT EXISTS is_online BOOLEAN NOT NULL DEFAULT TRUE;
+ALTER TABLE servers ADD COLUMN IF NOT EXISTS archived_at INTEGER;
+ALTER TABLE servers ADD COLUMN IF NOT EXISTS next_scan INTEGER;
+ALTER TABLE servers ADD COLUMN IF NOT EXISTS failure_rate DOUBLE PRECISION NOT NULL DEFAULT 0;
+ALTER TABLE player_sightings ALTER COLUMN uuid DROP NOT NULL;
+-- Favicons used to be stored in full on every server. They are moved to the favicons table with
+-- the same checks as favicon.rs before the column is dropped, so offline servers keep theirs
+DO $$
+DECLARE
+    server RECORD;
+    decoded BYTEA;
+    hash TEXT;
+BEGIN
+    IF NOT EXISTS (SELECT 1 FROM information_schema.columns WHERE table_name = 'servers' AND column_name = 'icon') THEN
+        RETURN;
+    END IF;
+
+    FOR server IN SELECT address, port, icon, first_seen FROM servers
+        WHERE icon LIKE 'data:image/png;base64,%' AND icon_hash IS NULL
+    LOOP
+        BEGIN
+            decoded := decode(regexp_replace(substring(server.icon FROM 23), '\s', '', 'g'), 'base64');
+        EXCEPTION WHEN OTHERS THEN
+            CONTINUE;
+        END;
+
+        -- PNG signature, then an IHDR chunk with a width and height of 64
+        IF length(decoded) < 24
+            OR substring(decoded FROM 1 FOR 8) <> '\x89504e470d0a1a0a'::bytea
+            OR substring(decoded FROM 13 FOR 4) <> 'IHDR'::bytea
+            OR substring(decoded FROM 17 FOR 8) <> '\x0000004000000040'::bytea
+        THEN
+            CONTINUE;
+        END IF;
+
+        hash := encode(sha256(decoded), 'hex');
+
+        INSERT INTO favicons (hash, png, first_seen) VALUES (hash, decoded, server.first_seen)
+        ON CONFLICT DO NOTHING;
+        UPDATE servers SET icon_hash = hash WHERE address = server.address AND port = server.port;
+    END LOOP;
+
+    ALTER TABLE servers DROP COLUMN icon;
+END $$;
+
 -- Create indexes for better performance
 CREATE INDEX IF NOT EXISTS idx_servers_last_seen ON servers(last_seen);
 CREATE INDEX IF NOT EXISTS idx_servers_country ON servers(country);
 CREATE INDEX IF NOT EXISTS idx_servers_software ON servers(software);
+CREATE INDEX IF NOT EXISTS idx_servers_latency_ms ON servers(latency_ms);
+CREATE INDEX IF NOT EXISTS idx_servers_icon_hash ON servers(icon_hash);
+CREATE INDEX IF NOT EXISTS idx_servers_auth_mode ON servers(auth_mode);
+CREATE INDEX IF NOT EXISTS idx_servers_suspected_honeypot ON servers(suspected_honeypot);
+CREATE INDEX IF NOT EXISTS idx_servers_is_online ON servers(is_online);
+CREATE INDEX IF NOT EXISTS idx_servers_archived_at ON servers(archived_at);
+CREATE INDEX IF NOT EXISTS idx_servers_next_scan ON servers(next_scan);
+CREATE INDEX IF NOT EXISTS idx_servers_description_plain ON servers USING GIN(description_plain gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS idx_server_snapshots_server ON server_snapshots(address, port, taken_at);
+CREATE INDEX IF NOT EXISTS idx_server_snapshots_taken_at ON server_snapshots(taken_at);
+CREATE INDEX IF NOT EXISTS idx_server_changes_server ON server_changes(address, port, changed_at);
+CREATE INDEX IF NOT EXISTS idx_server_changes_changed_at ON server_changes(changed_at);
+CREATE INDEX IF NOT EXISTS idx_bedrock_servers_last_seen ON bedrock_servers(last_seen);
 CREATE INDEX IF NOT EXISTS idx_players_name ON players(name);
 CREATE INDEX IF NOT EXISTS idx_players_uuid ON players(uuid);
+CREATE INDEX IF NOT EXISTS idx_player_sightings_name ON player_sightings(name);
+CREATE INDEX IF NOT EXISTS idx_player_sightings_uuid ON player_sightings(uuid);
+CREATE INDEX IF NOT EXISTS idx_player_sightings_seen_at ON player_sightings(seen_at);
+CREATE INDEX IF NOT EXISTS idx_query_players_name ON query_players(name);
+CREATE INDEX IF NOT EXISTS idx_plugins_name ON plugins(name);
 CREATE INDEX IF NOT EXISTS idx_countries_network ON countries USING GIST(network inet_ops);
 CREATE INDEX IF NOT EXISTS idx_server_visits_status ON server_visits(status);
 CREATE INDEX IF NOT EXISTS idx_server_visits_visited_at ON server_visits(visited_at);
\ No newline at end of file
@@ -0,0 +1,278 @@
+use crate::protocol::DEFAULT_TIMEOUT;
+use crate::response::OPT_OUT_MARKER;
+use crate::utils::RunError;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::net::UdpSocket;
+use tracing::debug;
+
+const UNCONNECTED_PING_ID: u8 = 0x01;
+const UNCONNECTED_PONG_ID: u8 = 0x1C;
+// Magic bytes present in every offline RakNet message
+const OFFLINE_MESSAGE_MAGIC: [u8; 16] = [
+	0x00, 0xFF, 0xFF, 0x00, 0xFE, 0xFE, 0xFE, 0xFE, 0xFD, 0xFD, 0xFD, 0xFD, 0x12, 0x34, 0x56, 0x78,
+];
+// Packet ID, time, server GUID, magic and string length
+const PONG_HEADER_SIZE: usize = 1 + 8 + 8 + 16 + 2;
+// Pongs larger than this would be fragmented
+const MAX_PONG_SIZE: usize = 1500;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct BedrockServer {
+	// "MCPE" for Bedrock Edition, "MCEE" for Education Edition
+	pub edition: String,
+	pub description: String,
+	pub protocol: i32,
+	pub version: String,
+	pub online_players: i32,
+	pub max_players: i32,
+	pub server_id: Option<String>,
+	// Second line of the description, usually the level name
+	pub level_name: Option<String>,
+	pub gamemode: Option<String>,
+	pub port_v4: Option<u16>,
+	pub port_v6: Option<u16>,
+}
+
+impl BedrockServer {
+	/// Parses the semicolon delimited string from an unconnected pong, e.g.
+	/// `MCPE;Dedicated Server;766;1.21.50;0;10;13253860892328930865;Bedrock level;Survival;1;19132;19133;`
+	pub fn parse(status: &str) -> Option<BedrockServer> {
+		let mut fields = status.split(';');
+
+		// Empty optional fields are treated as missing
+		let mut optional = || {
+			fields
+				.next()
+				.filter(|s| !s.is_empty())
+				.map(|s| s.to_string())
+		};
+
+		let edition = optional()?;
+		let description = optional().unwrap_or_default();
+		let protocol = optional()?.parse().ok()?;
+		let version = optional()?;
+		let online_players = optional()?.parse().ok()?;
+		let max_players = optional()?.parse().ok()?;
+		let server_id = optional();
+		let level_name = optional();
+		let gamemode = optional();
+
+		// Numeric gamemode is redundant with the gamemode name
+		optional();
+
+		let port_v4 = optional().and_then(|p| p.parse().ok());
+		let port_v6 = optional().and_then(|p| p.parse().ok());
+
+		Some(BedrockServer {
+			edition,
+			description,
+			protocol,
+			version,
+			online_players,
+			max_players,
+			server_id,
+			level_name,
+			gamemode,
+			port_v4,
+			port_v6,
+		})
+	}
+
+	// Has the user opted out of scanning?
+	pub fn check_opt_out(&self) -> bool {
+		self.description.contains(OPT_OUT_MARKER)
+	}
+}
+
+#[derive(Debug)]
+pub struct PingableBedrockServer {
+	pub socket: SocketAddrV4,
+	pub timeout: Duration,
+}
+
+impl PingableBedrockServer {
+	pub fn new(socket: SocketAddrV4) -> Self {
+		Self {
+			socket,
+			timeout: DEFAULT_TIMEOUT,
+		}
+	}
+
+	/// Sets how long to wait for each response
+	pub fn timeout(mut self, timeout: Duration) -> Self {
+		self.timeout = timeout;
+		self
+	}
+
+	/// Sends a RakNet unconnected ping and parses the servers pong
+	pub async fn ping(&self) -> Result<BedrockServer, RunError> {
+		let udp = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0)).await?;
+		udp.connect(self.socket).await?;
+
+		let time = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.unwrap_or_default()
+			.as_millis() as i64;
+
+		let mut ping = vec![UNCONNECTED_PING_ID];
+		ping.extend_from_slice(&time.to_be_bytes());
+		ping.extend_from_slice(&OFFLINE_MESSAGE_MAGIC);
+		// Client GUID, can be anything
+		ping.extend_from_slice(&0_i64.to_be_bytes());
+
+		udp.send(&ping).await?;
+
+		let mut response = [0; MAX_PONG_SIZE];
+		let total_read_bytes =
+			tokio::time::timeout(self.timeout, udp.recv(&mut response)).await??;
+		let response = &response[..total_read_bytes];
+
+		if total_read_bytes < PONG_HEADER_SIZE
+			|| response[0] != UNCONNECTED_PONG_ID
+			|| response[17..33] != OFFLINE_MESSAGE_MAGIC
+		{
+			debug!(
+				"[{}] Response was not an unconnected pong",
+				&self.socket.ip()
+			);
+			return Err(RunError::MalformedResponse);
+		}
+
+		let string_length = u16::from_be_bytes([response[33], response[34]]) as usize;
+		let string = &response[PONG_HEADER_SIZE..];
+
+		if string_length == 0 || string.len() < string_length {
+			debug!(
+				"[{}] String length: {string_length} was either 0 or too long",
+				&self.socket.ip()
+			);
+			return Err(RunError::MalformedResponse);
+		}
+
+		let status = String::from_utf8_lossy(&string[..string_length]);
+		BedrockServer::parse(&status).ok_or(RunError::MalformedResponse)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const STATUS: &str = "MCPE;Dedicated Server;766;1.21.50;3;10;13253860892328930865;Bedrock level;Survival;1;19132;19133;";
+
+	// Answers a single ping with the given packet, returns the address to ping
+	async fn stand_in(reply: impl FnOnce(&[u8]) -> Vec<u8> + Send + 'static) -> SocketAddrV4 {
+		let udp = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+		let socket = match udp.local_addr().unwrap() {
+			std::net::SocketAddr::V4(socket) => socket,
+			_ => unreachable!(),
+		};
+
+		tokio::spawn(async move {
+			let mut ping = [0; MAX_PONG_SIZE];
+			let (read, client) = udp.recv_from(&mut ping).await.unwrap();
+			udp.send_to(&reply(&ping[..read]), client).await.unwrap();
+		});
+
+		socket
+	}
+
+	fn pong(ping: &[u8], status: &str) -> Vec<u8> {
+		let mut pong = vec![UNCONNECTED_PONG_ID];
+		// Time from the ping, then the servers GUID
+		pong.extend_from_slice(&ping[1..9]);
+		pong.extend_from_slice(&1234_i64.to_be_bytes());
+		pong.extend_from_slice(&OFFLINE_MESSAGE_MAGIC);
+		pong.extend_from_slice(&(status.len() as u16).to_be_bytes());
+		pong.extend_from_slice(status.as_bytes());
+		pong
+	}
+
+	#[test]
+	fn parses_pong_string() {
+		let server = BedrockServer::parse(STATUS).unwrap();
+
+		assert_eq!(server.edition, "MCPE");
+		assert_eq!(server.description, "Dedicated Server");
+		assert_eq!(server.protocol, 766);
+		assert_eq!(server.version, "1.21.50");
+		assert_eq!(server.online_players, 3);
+		assert_eq!(server.max_players, 10);
+		assert_eq!(server.server_id.as_deref(), Some("13253860892328930865"));
+		assert_eq!(server.level_name.as_deref(), Some("Bedrock level"));
+		assert_eq!(server.gamemode.as_deref(), Some("Survival"));
+		assert_eq!(server.port_v4, Some(19132));
+		assert_eq!(server.port_v6, Some(19133));
+	}
+
+	#[test]
+	fn parses_short_pong_string() {
+		let server = BedrockServer::parse("MCEE;;390;1.14.0;0;40").unwrap();
+
+		assert_eq!(server.edition, "MCEE");
+		assert_eq!(server.description, "");
+		assert_eq!(server.server_id, None);
+		assert_eq!(server.port_v4, None);
+	}
+
+	#[test]
+	fn rejects_incomplete_pong_string() {
+		assert_eq!(
+			BedrockServer::parse("MCPE;Dedicated Server;766;1.21.50;3"),
+			None
+		);
+		assert_eq!(
+			BedrockServer::parse("MCPE;Dedicated Server;new;1.21.50;3;10"),
+			None
+		);
+		assert_eq!(BedrockServer::parse(""), None);
+	}
+
+	#[test]
+	fn detects_opt_out() {
+		let opted_out = STATUS.replace("Dedicated Server", "§b§d§f§d§bDedicated Server");
+
+		assert!(BedrockServer::parse(&opted_out).unwrap().check_opt_out());
+		assert!(!BedrockServer::parse(STATUS).unwrap().check_opt_out());
+	}
+
+	#[tokio::test]
+	async fn pings_stand_in() {
+		let socket = stand_in(|ping| {
+			assert_eq!(ping[0], UNCONNECTED_PING_ID);
+			assert_eq!(ping[9..25], OFFLINE_MESSAGE_MAGIC);
+			pong(ping, STATUS)
+		})
+		.await;
+
+		let server = PingableBedrockServer::new(socket).ping().await.unwrap();
+		assert_eq!(server, BedrockServer::parse(STATUS).unwrap());
+	}
+
+	#[tokio::test]
+	async fn rejects_other_packets() {
+		let socket = stand_in(|ping| {
+			let mut pong = pong(ping, STATUS);
+			pong[0] = UNCONNECTED_PING_ID;
+			pong
+		})
+		.await;
+
+		let result = PingableBedrockServer::new(socket).ping().await;
+		assert!(matches!(result, Err(RunError::MalformedResponse)));
+	}
+
+	#[tokio::test]
+	async fn rejects_truncated_string() {
+		let socket = stand_in(|ping| {
+			let mut pong = pong(ping, STATUS);
+			pong.truncate(pong.len() - 10);
+			pong
+		})
+		.await;
+
+		let result = PingableBedrockServer::new(socket).ping().await;
+		assert!(matches!(result, Err(RunError::MalformedResponse)));
+	}
+}
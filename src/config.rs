@@ -1,7 +1,11 @@
+use crate::notifications::EventKind;
+use crate::protocol::{DEFAULT_PROTOCOL_VERSION, DEFAULT_TIMEOUT};
 use serde::Deserialize;
 use std::cmp::max;
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::{ErrorKind, Read};
+use std::net::Ipv4Addr;
 use tracing::error;
 
 #[derive(Deserialize, Clone, Debug)]
@@ -9,8 +13,20 @@ pub struct Config {
 	pub database: Database,
 	pub scanner: ScannerConfig,
 	pub masscan: Masscan,
+	#[serde(default)]
+	pub discovery: Discovery,
+	#[serde(default)]
+	pub exclusions: Exclusions,
+	#[serde(default)]
+	pub rescan: Rescan,
 	pub player_tracking: PlayerTracking,
 	pub country_tracking: CountryTracking,
+	#[serde(default)]
+	pub notifications: Notifications,
+	#[serde(default)]
+	pub history: History,
+	#[serde(default)]
+	pub pruning: Pruning,
 }
 
 #[derive(Deserialize, Clone, Debug)]
@@ -28,6 +44,35 @@ pub struct ScannerConfig {
 	pub scan_delay: u64,
 	pub port_range_start: u16,
 	pub port_range_end: u16,
+	// Send a full handshake instead of the minimal one
+	#[serde(default)]
+	pub proper_ping: bool,
+	#[serde(default = "default_protocol_version")]
+	pub protocol_version: i32,
+	// Send a ping packet after the status response to measure latency
+	#[serde(default)]
+	pub measure_latency: bool,
+	// Request the full player and plugin list from servers with enable-query=true
+	#[serde(default)]
+	pub query: bool,
+	// Attempt to log in to find out if a server is online mode, offline mode or whitelisted
+	#[serde(default)]
+	pub login_probe: bool,
+	#[serde(default = "default_login_username")]
+	pub login_username: String,
+	// Hostnames to send in the handshake for specific addresses
+	#[serde(default)]
+	pub hostnames: HashMap<Ipv4Addr, String>,
+	#[serde(default = "default_timeout_ms")]
+	pub connect_timeout_ms: u64,
+	#[serde(default = "default_timeout_ms")]
+	pub read_timeout_ms: u64,
+	// Servers being pinged at once
+	#[serde(default = "default_max_in_flight")]
+	pub max_in_flight: usize,
+	// Servers being pinged at once in the same /24, 0 for no limit
+	#[serde(default)]
+	pub max_per_subnet: usize,
 }
 
 #[derive(Deserialize, Clone, Debug)]
@@ -35,6 +80,65 @@ pub struct Masscan {
 	pub config_file: String,
 }
 
+#[derive(Deserialize, Clone, Debug)]
+pub struct Discovery {
+	#[serde(default)]
+	pub engine: DiscoveryEngine,
+	// CIDR ranges or single addresses to scan with the native engine
+	#[serde(default)]
+	pub ranges: Vec<String>,
+	#[serde(default = "default_discovery_ports")]
+	pub ports: Vec<u16>,
+	// Connections started per second with the native engine
+	#[serde(default = "default_discovery_rate")]
+	pub rate: u64,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DiscoveryEngine {
+	// Runs masscan with sudo, using the config file from [masscan]
+	#[default]
+	Masscan,
+	// Connects to every address itself, no root or external programs needed
+	Native,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct Exclusions {
+	// Private, reserved, multicast and DoD networks
+	#[serde(default = "default_true")]
+	pub default_exclusions: bool,
+	// File with one CIDR range or address per line
+	#[serde(default)]
+	pub file: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct Rescan {
+	#[serde(default)]
+	pub schedule: RescanSchedule,
+	// Seconds between rescans of the busiest servers, everything else is rescanned less often
+	#[serde(default = "default_min_interval")]
+	pub min_interval: u64,
+	// Longest a server goes without being rescanned, however long it's been offline
+	#[serde(default = "default_max_interval")]
+	pub max_interval: u64,
+	// Servers with at least this many players online are rescanned every min_interval
+	#[serde(default = "default_busy_players")]
+	pub busy_players: i32,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RescanSchedule {
+	// Every server in the database, then wait scan_delay and start again
+	#[default]
+	Full,
+	// Each server when it's due, based on its players and how long it's been offline
+	Adaptive,
+}
+
 #[derive(Deserialize, Clone, Debug)]
 pub struct PlayerTracking {
 	pub enabled: bool,
@@ -48,6 +152,160 @@ pub struct CountryTracking {
 	pub ipinfo_token: String,
 }
 
+#[derive(Deserialize, Clone, Debug)]
+pub struct History {
+	pub enabled: bool,
+	// Minimum seconds between two snapshots of the same server
+	#[serde(default = "default_sample_interval")]
+	pub sample_interval: u64,
+	// Snapshots older than this are thinned out to the busiest one of each day, 0 to never
+	#[serde(default = "default_downsample_after_days")]
+	pub downsample_after_days: u64,
+	// Snapshots older than this are deleted, 0 to keep them forever
+	#[serde(default = "default_retention_days")]
+	pub retention_days: u64,
+	// Changes older than this are deleted, 0 to keep them forever
+	#[serde(default)]
+	pub change_retention_days: u64,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct Pruning {
+	// Failed pings in a row before a server is marked offline
+	#[serde(default = "default_offline_after_failures")]
+	pub offline_after_failures: i32,
+	// What happens to servers that haven't been seen for unseen_days
+	#[serde(default)]
+	pub action: PruneAction,
+	#[serde(default = "default_unseen_days")]
+	pub unseen_days: u64,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PruneAction {
+	#[default]
+	Keep,
+	// Archived servers are kept in the database but no longer rescanned
+	Archive,
+	// Along with everything else stored about them, servers with visits are archived instead
+	Delete,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct Notifications {
+	pub enabled: bool,
+	// Events waiting to be sent to each webhook, any more than this are dropped
+	#[serde(default = "default_queue_size")]
+	pub queue_size: usize,
+	// How many times a failed request is retried
+	#[serde(default = "default_retries")]
+	pub retries: u32,
+	#[serde(default)]
+	pub webhooks: Vec<Webhook>,
+	// New servers matching any of these are sent as a "new_server" event
+	#[serde(default)]
+	pub filters: Vec<ServerFilter>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct Webhook {
+	pub url: String,
+	#[serde(default)]
+	pub format: WebhookFormat,
+	// Events to send to this webhook, all of them if empty
+	#[serde(default)]
+	pub events: Vec<EventKind>,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookFormat {
+	// The event itself as JSON
+	#[default]
+	Json,
+	// A message for a Discord webhook
+	Discord,
+}
+
+// Every field that is set has to match
+#[derive(Deserialize, Clone, Debug)]
+pub struct ServerFilter {
+	pub name: String,
+	pub software: Option<String>,
+	// Version name contains
+	pub version: Option<String>,
+	// Description contains
+	pub description: Option<String>,
+	pub country: Option<String>,
+	pub min_players: Option<i32>,
+}
+
+impl Default for Discovery {
+	fn default() -> Self {
+		Discovery {
+			engine: DiscoveryEngine::default(),
+			ranges: vec![],
+			ports: default_discovery_ports(),
+			rate: default_discovery_rate(),
+		}
+	}
+}
+
+impl Default for Exclusions {
+	fn default() -> Self {
+		Exclusions {
+			default_exclusions: true,
+			file: None,
+		}
+	}
+}
+
+impl Default for Rescan {
+	fn default() -> Self {
+		Rescan {
+			schedule: RescanSchedule::default(),
+			min_interval: default_min_interval(),
+			max_interval: default_max_interval(),
+			busy_players: default_busy_players(),
+		}
+	}
+}
+
+impl Default for History {
+	fn default() -> Self {
+		History {
+			enabled: false,
+			sample_interval: default_sample_interval(),
+			downsample_after_days: default_downsample_after_days(),
+			retention_days: default_retention_days(),
+			change_retention_days: 0,
+		}
+	}
+}
+
+impl Default for Pruning {
+	fn default() -> Self {
+		Pruning {
+			offline_after_failures: default_offline_after_failures(),
+			action: PruneAction::default(),
+			unseen_days: default_unseen_days(),
+		}
+	}
+}
+
+impl Default for Notifications {
+	fn default() -> Self {
+		Notifications {
+			enabled: false,
+			queue_size: default_queue_size(),
+			retries: default_retries(),
+			webhooks: vec![],
+			filters: vec![],
+		}
+	}
+}
+
 impl Default for Config {
 	fn default() -> Self {
 		Config {
@@ -63,10 +321,24 @@ impl Default for Config {
 				scan_delay: 60,
 				port_range_start: 25565,
 				port_range_end: 25565,
+				proper_ping: false,
+				protocol_version: DEFAULT_PROTOCOL_VERSION,
+				measure_latency: false,
+				query: false,
+				login_probe: false,
+				login_username: default_login_username(),
+				hostnames: HashMap::new(),
+				connect_timeout_ms: default_timeout_ms(),
+				read_timeout_ms: default_timeout_ms(),
+				max_in_flight: default_max_in_flight(),
+				max_per_subnet: 0,
 			},
 			masscan: Masscan {
 				config_file: "masscan.conf".to_string(),
 			},
+			discovery: Discovery::default(),
+			exclusions: Exclusions::default(),
+			rescan: Rescan::default(),
 			player_tracking: PlayerTracking {
 				enabled: false,
 				players: vec![],
@@ -76,6 +348,9 @@ impl Default for Config {
 				update_frequency: 48,
 				ipinfo_token: "".to_string(),
 			},
+			notifications: Notifications::default(),
+			history: History::default(),
+			pruning: Pruning::default(),
 		}
 	}
 }
@@ -94,9 +369,179 @@ impl ScannerConfig {
 	}
 }
 
+// Servers first seen less than this many seconds ago are never left as long as empty ones
+const NEW_SERVER_AGE: u64 = 86400;
+
+impl Rescan {
+	/// Seconds until a server that answered is rescanned. Quiet servers wait three times as long
+	/// as busy ones and empty servers six times, unless the server is new. On top of that, servers
+	/// that failed most of their recent pings wait up to four times as long
+	pub fn online_interval(&self, online_players: i32, age: u64, failure_rate: f64) -> u64 {
+		let mut factor = match online_players {
+			n if n >= self.busy_players => 1,
+			n if n > 0 => 3,
+			_ => 6,
+		};
+
+		// New servers often get their first players soon after they go up
+		if age < NEW_SERVER_AGE {
+			factor = factor.min(3);
+		}
+
+		let flakiness = 1.0 + 3.0 * failure_rate.clamp(0.0, 1.0);
+		let interval = (self.min_interval * factor) as f64 * flakiness;
+
+		(interval as u64).min(self.max_interval)
+	}
+}
+
+fn default_protocol_version() -> i32 {
+	DEFAULT_PROTOCOL_VERSION
+}
+
+fn default_login_username() -> String {
+	"ServerSeekerV2".to_string()
+}
+
+fn default_timeout_ms() -> u64 {
+	DEFAULT_TIMEOUT.as_millis() as u64
+}
+
+fn default_max_in_flight() -> usize {
+	1000
+}
+
+fn default_discovery_ports() -> Vec<u16> {
+	vec![25565]
+}
+
+fn default_discovery_rate() -> u64 {
+	1000
+}
+
+fn default_true() -> bool {
+	true
+}
+
+fn default_min_interval() -> u64 {
+	600
+}
+
+fn default_max_interval() -> u64 {
+	60 * 60 * 24 * 7
+}
+
+fn default_busy_players() -> i32 {
+	10
+}
+
+fn default_sample_interval() -> u64 {
+	3600
+}
+
+fn default_downsample_after_days() -> u64 {
+	7
+}
+
+fn default_retention_days() -> u64 {
+	365
+}
+
+fn default_offline_after_failures() -> i32 {
+	2
+}
+
+fn default_unseen_days() -> u64 {
+	90
+}
+
+fn default_queue_size() -> usize {
+	1000
+}
+
+fn default_retries() -> u32 {
+	3
+}
+
 pub fn load_config(path: &str) -> Result<Config, std::io::Error> {
 	let mut file = File::open(path)?;
 	let mut contents = String::new();
 	file.read_to_string(&mut contents).unwrap_or_default();
 	toml::from_str(&contents).map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// Old enough to not count as a new server
+	const ESTABLISHED: u64 = 30 * 86400;
+
+	fn rescan(min_interval: u64, max_interval: u64) -> Rescan {
+		Rescan {
+			schedule: RescanSchedule::Adaptive,
+			min_interval,
+			max_interval,
+			busy_players: 10,
+		}
+	}
+
+	#[test]
+	fn busy_servers_are_rescanned_every_min_interval() {
+		let rescan = rescan(600, 604800);
+		assert_eq!(rescan.online_interval(10, ESTABLISHED, 0.0), 600);
+		assert_eq!(rescan.online_interval(500, ESTABLISHED, 0.0), 600);
+	}
+
+	#[test]
+	fn quiet_servers_wait_three_times_as_long() {
+		let rescan = rescan(600, 604800);
+		assert_eq!(rescan.online_interval(1, ESTABLISHED, 0.0), 1800);
+		assert_eq!(rescan.online_interval(9, ESTABLISHED, 0.0), 1800);
+	}
+
+	#[test]
+	fn empty_servers_wait_six_times_as_long() {
+		let rescan = rescan(600, 604800);
+		assert_eq!(rescan.online_interval(0, ESTABLISHED, 0.0), 3600);
+		// Servers that hide their player count can report negative numbers
+		assert_eq!(rescan.online_interval(-1, ESTABLISHED, 0.0), 3600);
+	}
+
+	#[test]
+	fn new_empty_servers_are_rescanned_like_quiet_ones() {
+		let rescan = rescan(600, 604800);
+		assert_eq!(rescan.online_interval(0, 0, 0.0), 1800);
+		assert_eq!(rescan.online_interval(0, NEW_SERVER_AGE - 1, 0.0), 1800);
+		assert_eq!(rescan.online_interval(0, NEW_SERVER_AGE, 0.0), 3600);
+
+		// Busy and quiet servers keep their interval
+		assert_eq!(rescan.online_interval(10, 0, 0.0), 600);
+		assert_eq!(rescan.online_interval(1, 0, 0.0), 1800);
+	}
+
+	#[test]
+	fn flaky_servers_wait_longer() {
+		let rescan = rescan(600, 604800);
+		assert_eq!(rescan.online_interval(10, ESTABLISHED, 0.5), 1500);
+		assert_eq!(rescan.online_interval(10, ESTABLISHED, 1.0), 2400);
+		assert_eq!(rescan.online_interval(0, ESTABLISHED, 1.0), 14400);
+
+		// Out of range rates are clamped
+		assert_eq!(rescan.online_interval(10, ESTABLISHED, -1.0), 600);
+		assert_eq!(rescan.online_interval(10, ESTABLISHED, 2.0), 2400);
+	}
+
+	#[test]
+	fn intervals_never_exceed_max_interval() {
+		let short = rescan(600, 2000);
+		assert_eq!(short.online_interval(10, ESTABLISHED, 0.0), 600);
+		assert_eq!(short.online_interval(1, ESTABLISHED, 0.0), 1800);
+		assert_eq!(short.online_interval(0, ESTABLISHED, 0.0), 2000);
+		assert_eq!(short.online_interval(1, ESTABLISHED, 1.0), 2000);
+
+		// Even busy servers once min_interval is the larger of the two
+		let slow = rescan(3000, 2000);
+		assert_eq!(slow.online_interval(10, ESTABLISHED, 0.0), 2000);
+	}
+}
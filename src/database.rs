@@ -1,13 +1,32 @@
+use crate::bedrock::BedrockServer;
+use crate::config::{History, Notifications, PlayerTracking, PruneAction, Pruning, Rescan};
+use crate::exclusions::Exclusions;
+use crate::favicon::Favicon;
 use crate::geo_lookup::GeoLookup;
-use crate::response::Server;
+use crate::history::{self, TrackedFields};
+use crate::honeypot::{self, HoneypotReason};
+use crate::notifications::{Event, Notifier};
+use crate::player_tracking::TrackedPlayers;
+use crate::pruning;
+use crate::response::{Player, Server};
 use crate::utils::RunError;
 use sqlx::postgres::{PgQueryResult, PgRow};
 use sqlx::types::ipnet::{IpNet, Ipv4Net};
 use sqlx::types::Uuid;
 use sqlx::{FromRow, PgPool, Row};
-use std::net::{IpAddr, SocketAddrV4};
+use std::collections::HashSet;
+use std::net::{IpAddr, Ipv4Addr, SocketAddrV4};
 use std::sync::Arc;
 use std::time::{SystemTime, UNIX_EPOCH};
+use text_component::Component;
+use tracing::{info, warn};
+
+// How much the latest ping counts towards a server's failure_rate, older pings fade out
+const FAILURE_WEIGHT: f64 = 0.2;
+
+// Everything a canned response would have in common, for servers inside the network in $1
+const SAME_RESPONSE: &str = "address <<= $1 AND description_plain IS NOT DISTINCT FROM $2
+	AND version = $3 AND protocol = $4 AND online_players = $5 AND max_players = $6";
 
 #[derive(Debug)]
 struct AddressInfo {
@@ -28,6 +47,11 @@ impl FromRow<'_, PgRow> for AddressInfo {
 pub struct Database {
 	pub pool: PgPool,
 	pub geo_lookup: Arc<GeoLookup>,
+	pub tracked_players: Arc<TrackedPlayers>,
+	pub notifier: Notifier,
+	pub history: History,
+	pub pruning: Pruning,
+	pub rescan: Rescan,
 }
 
 impl Database {
@@ -35,7 +59,61 @@ impl Database {
 		Self {
 			pool,
 			geo_lookup: Arc::new(GeoLookup::new()),
+			tracked_players: Arc::new(TrackedPlayers::default()),
+			notifier: Notifier::default(),
+			history: History::default(),
+			pruning: Pruning::default(),
+			rescan: Rescan::default(),
+		}
+	}
+
+	/// Records a sighting whenever one of these players is in a servers player sample
+	pub fn track_players(mut self, config: &PlayerTracking) -> Self {
+		let tracked_players = TrackedPlayers::from(config);
+
+		if config.enabled && tracked_players.is_empty() {
+			warn!("Player tracking is enabled but no players are listed in the config file");
+		}
+
+		self.tracked_players = Arc::new(tracked_players);
+		self
+	}
+
+	/// Sends events about servers and players to the webhooks in the config
+	pub fn notifications(mut self, config: &Notifications) -> Self {
+		if config.enabled && config.webhooks.is_empty() {
+			warn!("Notifications are enabled but no webhooks are listed in the config file");
 		}
+
+		self.notifier = Notifier::new(config);
+		self
+	}
+
+	/// Keeps snapshots and a change log of every server, old history is pruned in the background
+	pub fn history(mut self, config: &History) -> Self {
+		if config.enabled {
+			tokio::spawn(history::prune(self.pool.clone(), config.clone()));
+		}
+
+		self.history = config.clone();
+		self
+	}
+
+	/// Marks servers offline after failed pings, servers that haven't been seen
+	/// for a while are archived or deleted in the background
+	pub fn pruning(mut self, config: &Pruning) -> Self {
+		if config.action != PruneAction::Keep {
+			tokio::spawn(pruning::prune(self.pool.clone(), config.clone()));
+		}
+
+		self.pruning = config.clone();
+		self
+	}
+
+	/// Decides when each server is due to be rescanned after it's pinged
+	pub fn rescan(mut self, config: &Rescan) -> Self {
+		self.rescan = config.clone();
+		self
 	}
 
 	/// Gets the count of servers from database
@@ -48,12 +126,83 @@ impl Database {
 		Ok(result)
 	}
 
-	/// Deletes a server from the database
-	async fn delete_server(&self, address: IpNet) -> Result<PgQueryResult, sqlx::Error> {
-		sqlx::query("DELETE FROM servers WHERE address = $1")
+	/// Deletes a server from the database and records that it's opted out, so
+	/// it's never scanned again even if the marker is removed from its description.
+	/// Only the edition that showed the marker is opted out, the other may be someone else's
+	async fn opt_out(
+		&self,
+		bedrock: bool,
+		address: IpNet,
+		port: u16,
+		timestamp: i32,
+	) -> Result<(), sqlx::Error> {
+		let (opt_outs, servers) = match bedrock {
+			true => ("bedrock_opt_outs", "bedrock_servers"),
+			false => ("opt_outs", "servers"),
+		};
+
+		let mut transaction = self.pool.begin().await?;
+
+		sqlx::query(&format!("INSERT INTO {opt_outs} (address, port, opted_out_at) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING"))
 			.bind(address)
-			.execute(&self.pool)
-			.await
+			.bind(port as i32)
+			.bind(timestamp)
+			.execute(&mut *transaction)
+			.await?;
+
+		sqlx::query(&format!(
+			"DELETE FROM {servers} WHERE address = $1 AND port = $2"
+		))
+		.bind(address)
+		.bind(port as i32)
+		.execute(&mut *transaction)
+		.await?;
+
+		transaction.commit().await
+	}
+
+	/// Claims up to `limit` servers that are due to be rescanned, they are pushed back
+	/// by min_interval so they aren't claimed again while they are being pinged.
+	/// Excluded and opted out servers are never claimed, so they don't come back every min_interval
+	pub async fn claim_due_servers(
+		&self,
+		timestamp: i32,
+		limit: i64,
+		exclusions: &Exclusions,
+	) -> Result<Vec<SocketAddrV4>, sqlx::Error> {
+		// Configured networks aren't in the database, so they are passed as ranges of addresses
+		let (first, last): (Vec<i64>, Vec<i64>) = exclusions
+			.ranges()
+			.iter()
+			.map(|(first, last)| (*first as i64, *last as i64))
+			.unzip();
+
+		let rows: Vec<(i64, i32)> = sqlx::query_as(
+			"UPDATE servers SET next_scan = $1 + $2 WHERE (address, port) IN (
+			SELECT address, port FROM servers
+			WHERE archived_at IS NULL AND (next_scan IS NULL OR next_scan <= $1)
+			AND NOT EXISTS (SELECT 1 FROM scan_exclusions WHERE servers.address <<= network)
+			AND NOT EXISTS (SELECT 1 FROM opt_outs WHERE opt_outs.address = servers.address AND opt_outs.port = servers.port)
+			AND NOT EXISTS (SELECT 1 FROM unnest($4::bigint[], $5::bigint[]) AS excluded (first, last)
+				WHERE (servers.address - '0.0.0.0'::inet) BETWEEN excluded.first AND excluded.last)
+			ORDER BY next_scan ASC NULLS FIRST LIMIT $3
+			FOR UPDATE SKIP LOCKED)
+			RETURNING (address - '0.0.0.0'::inet) AS address, port",
+		)
+		.bind(timestamp)
+		.bind(self.rescan.min_interval as i32)
+		.bind(limit)
+		.bind(first)
+		.bind(last)
+		.fetch_all(&self.pool)
+		.await?;
+
+		Ok(rows
+			.into_iter()
+			.map(|(address, port)| {
+				SocketAddrV4::new(Ipv4Addr::from_bits(address as u32), port as u16)
+			})
+			.collect())
 	}
 
 	async fn get_country_info(&self, address: &IpNet) -> Result<AddressInfo, sqlx::Error> {
@@ -87,14 +236,95 @@ impl Database {
 			.description_raw
 			.as_ref()
 			.map(|v| server.build_formatted_description(v));
+		let plain = server
+			.description_raw
+			.as_ref()
+			.map(|v| Component::from(v).to_plain());
 
 		// Delete server if it's opted out
 		if server.check_opt_out() {
-			self.delete_server(address).await?;
+			info!("[{}] Server opted out of scanning", socket);
+			self.opt_out(false, address, socket.port(), timestamp)
+				.await?;
 			return Err(RunError::ServerOptOut)?;
 		}
 
+		// Suspected honeypots are still stored, but flagged and without their players
+		let honeypot = match honeypot::check_response(&server) {
+			Some(reason) => Some(reason),
+			None => {
+				self.check_repeated_response(socket, &server, plain.as_deref())
+					.await?
+			}
+		};
+
+		if let Some(reason) = honeypot {
+			info!("[{}] Suspected honeypot: {reason:?}", socket);
+		}
+
 		let address_information = self.get_country_info(&address).await?;
+		let auth_mode = server.auth_mode();
+
+		// Favicons are stored once and referenced by their hash
+		let favicon = server.favicon.as_deref().and_then(Favicon::parse);
+		if let Some(favicon) = &favicon {
+			sqlx::query("INSERT INTO favicons (hash, png, first_seen) VALUES ($1, $2, $3) ON CONFLICT (hash) DO NOTHING")
+				.bind(&favicon.hash)
+				.bind(&favicon.png)
+				.bind(timestamp)
+				.execute(&self.pool)
+				.await?;
+		}
+
+		let current = TrackedFields {
+			software: Some(format!("{:?}", server.get_type())),
+			version: Some(server.version.name.clone()),
+			motd_hash: formatted.as_deref().map(history::motd_hash),
+			icon_hash: favicon.as_ref().map(|f| f.hash.clone()),
+		};
+
+		// What was stored before is only needed for the change log and notifications
+		let previous: Option<TrackedFields> =
+			match self.history.enabled || self.notifier.is_enabled() {
+				true => sqlx::query_as(
+					"SELECT software::text AS software, version, md5(description_formatted) AS motd_hash, icon_hash
+					FROM servers WHERE address = $1 AND port = $2",
+				)
+				.bind(address)
+				.bind(socket.port() as i32)
+				.fetch_optional(&self.pool)
+				.await?,
+				false => None,
+			};
+		let changes = previous.map(|p| p.changes(&current));
+
+		// Older servers and ones that often fail to answer are rescanned less often
+		let (first_seen, failure_rate): (i32, f64) = sqlx::query_as(
+			"SELECT first_seen, failure_rate FROM servers WHERE address = $1 AND port = $2",
+		)
+		.bind(address)
+		.bind(socket.port() as i32)
+		.fetch_optional(&self.pool)
+		.await?
+		.unwrap_or((timestamp, 0.0));
+		let next_scan = timestamp
+			+ self.rescan.online_interval(
+				server.players.online,
+				timestamp.saturating_sub(first_seen).max(0) as u64,
+				failure_rate,
+			) as i32;
+
+		// Suspected honeypots would only be noise
+		let events = match honeypot {
+			Some(_) => vec![],
+			None => self.notifier.server_events(
+				socket,
+				changes.as_deref(),
+				&server,
+				plain.as_deref(),
+				&address_information.country,
+			),
+		};
 
 		sqlx::query(
 			"INSERT INTO servers (
@@ -103,9 +333,10 @@ impl Database {
 		software,
         version,
 		protocol,
-		icon,
+		icon_hash,
 		description_raw,
         description_formatted,
+		description_plain,
 		prevents_chat_reports,
 		enforces_secure_chat,
 		first_seen,
@@ -113,34 +344,60 @@ impl Database {
 		online_players,
 		max_players,
         country,
-    	asn) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16)
+    	asn,
+		latency_ms,
+		login_status,
+		disconnect_reason,
+		mods_truncated,
+		suspected_honeypot,
+		sample_text,
+		hides_players,
+		auth_mode,
+		next_scan) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20, $21, $22, $23, $24, $25, $26)
     	ON CONFLICT (address, port) DO UPDATE SET
     	software = EXCLUDED.software,
     	version = EXCLUDED.version,
     	protocol = EXCLUDED.protocol,
-    	icon = EXCLUDED.icon,
+    	icon_hash = EXCLUDED.icon_hash,
     	description_raw = EXCLUDED.description_raw,
     	description_formatted = EXCLUDED.description_formatted,
+    	description_plain = EXCLUDED.description_plain,
     	prevents_chat_reports = EXCLUDED.prevents_chat_reports,
     	enforces_secure_chat = EXCLUDED.enforces_secure_chat,
     	last_seen = EXCLUDED.last_seen,
     	online_players = EXCLUDED.online_players,
     	max_players = EXCLUDED.max_players,
     	country = EXCLUDED.country,
-    	asn = EXCLUDED.asn",
+    	asn = EXCLUDED.asn,
+    	latency_ms = COALESCE(EXCLUDED.latency_ms, servers.latency_ms),
+    	login_status = COALESCE(EXCLUDED.login_status, servers.login_status),
+    	disconnect_reason = CASE WHEN EXCLUDED.login_status IS NULL
+    		THEN servers.disconnect_reason ELSE EXCLUDED.disconnect_reason END,
+    	mods_truncated = EXCLUDED.mods_truncated,
+    	suspected_honeypot = EXCLUDED.suspected_honeypot,
+    	sample_text = EXCLUDED.sample_text,
+    	hides_players = EXCLUDED.hides_players,
+    	auth_mode = COALESCE(EXCLUDED.auth_mode, servers.auth_mode),
+    	consecutive_failures = 0,
+    	failure_rate = servers.failure_rate * (1 - $27::float8),
+    	is_online = TRUE,
+    	archived_at = NULL,
+    	next_scan = EXCLUDED.next_scan",
 		)
 		.bind(address)
 		.bind(socket.port() as i32)
 		.bind(server.get_type())
-		.bind(server.version.name)
+		.bind(&server.version.name)
 		.bind(server.version.protocol)
-		.bind(server.favicon)
+		.bind(favicon.map(|f| f.hash))
 		// description_raw is for storing raw JSON descriptions
 		// useful for applications that want to parse descriptions in their own way
 		.bind(server.description_raw)
 		// description_formatted is for pre-formatted descriptions
 		// useful for regex searches and for applications that just quickly need a servers description
 		.bind(formatted)
+		// description_plain has no formatting at all, for searching descriptions
+		.bind(plain)
 		.bind(server.prevents_reports)
 		.bind(server.enforces_secure_chat)
 		.bind(timestamp)
@@ -149,11 +406,65 @@ impl Database {
 		.bind(server.players.max)
 		.bind(address_information.country)
 		.bind(address_information.asn)
+		.bind(server.latency_ms)
+		.bind(server.login.as_ref().map(|l| l.status))
+		.bind(server.login.and_then(|l| l.disconnect_reason))
+		.bind(server.forge_data.as_ref().map(|f| f.truncated))
+		.bind(honeypot)
+		// Text a server puts in the player sample, shown when hovering over the player count
+		.bind(server.players.sample_text())
+		.bind(server.players.hides_players())
+		.bind(auth_mode)
+		.bind(next_scan)
+		.bind(FAILURE_WEIGHT)
 		.execute(&self.pool)
 		.await?;
 
+		for event in events {
+			self.notifier.send(event);
+		}
+
+		if honeypot.is_some() {
+			return Ok(());
+		}
+
+		if self.history.enabled {
+			// Servers that are rescanned often would fill the table, so only one is kept per sample_interval
+			sqlx::query("INSERT INTO server_snapshots (address, port, taken_at, online_players, max_players, software, version, protocol, latency_ms)
+			SELECT $1, $2, $3, $4, $5, $6::software, $7, $8, $9
+			WHERE NOT EXISTS (SELECT 1 FROM server_snapshots WHERE address = $1 AND port = $2 AND taken_at > $3 - $10)")
+				.bind(address)
+				.bind(socket.port() as i32)
+				.bind(timestamp)
+				.bind(server.players.online)
+				.bind(server.players.max)
+				.bind(&current.software)
+				.bind(&current.version)
+				.bind(server.version.protocol)
+				.bind(server.latency_ms)
+				.bind(self.history.sample_interval as i32)
+				.execute(&self.pool)
+				.await?;
+
+			for change in changes.iter().flatten() {
+				sqlx::query("INSERT INTO server_changes (address, port, changed_at, field, old, new) VALUES ($1, $2, $3, $4, $5, $6)")
+					.bind(address)
+					.bind(socket.port() as i32)
+					.bind(timestamp)
+					.bind(change.field)
+					.bind(&change.old)
+					.bind(&change.new)
+					.execute(&self.pool)
+					.await?;
+			}
+		}
+
+		// Names of tracked players already seen in the sample, so the query list doesn't report them again
+		let mut sighted = HashSet::new();
+
 		if let Some(sample) = server.players.sample {
-			for player in sample {
+			// Text lines and anonymous players are kept out of the players table
+			for player in sample.into_iter().filter(Player::is_real) {
 				if let Ok(uuid) = Uuid::parse_str(&player.id) {
 					sqlx::query("INSERT INTO players (address, port, uuid, name, first_seen, last_seen) VALUES ($1, $2, $3, $4, $5, $6)
 	                ON CONFLICT (address, port, uuid) DO UPDATE SET
@@ -161,12 +472,56 @@ impl Database {
 						.bind(address)
 						.bind(socket.port() as i32)
 						.bind(uuid)
-						.bind(player.name)
+						.bind(&player.name)
 						.bind(timestamp)
 						.bind(timestamp)
 						.execute(&self.pool)
 						.await?;
+
+					if self.tracked_players.contains(&player) {
+						sighted.insert(player.name.to_lowercase());
+						self.player_seen(socket, &player.name, Some(uuid), timestamp)
+							.await?;
+					}
+				}
+			}
+		}
+
+		if let Some(query) = server.query {
+			for plugin in query.plugins {
+				sqlx::query("INSERT INTO plugins (address, port, name, version, first_seen, last_seen) VALUES ($1, $2, $3, $4, $5, $6)
+				ON CONFLICT (address, port, name) DO UPDATE SET
+				version = EXCLUDED.version,
+				last_seen = EXCLUDED.last_seen")
+					.bind(address)
+					.bind(socket.port() as i32)
+					.bind(plugin.name)
+					.bind(plugin.version)
+					.bind(timestamp)
+					.bind(timestamp)
+					.execute(&self.pool)
+					.await?;
+			}
+
+			// The query protocol only gives names, so these can't go in the players table
+			for name in query.players {
+				// Large servers often cap or hide the sample, this list has everyone online
+				if self.tracked_players.contains_name(&name)
+					&& !sighted.contains(&name.to_lowercase())
+				{
+					self.player_seen(socket, &name, None, timestamp).await?;
 				}
+
+				sqlx::query("INSERT INTO query_players (address, port, name, first_seen, last_seen) VALUES ($1, $2, $3, $4, $5)
+				ON CONFLICT (address, port, name) DO UPDATE SET
+				last_seen = EXCLUDED.last_seen")
+					.bind(address)
+					.bind(socket.port() as i32)
+					.bind(name)
+					.bind(timestamp)
+					.bind(timestamp)
+					.execute(&self.pool)
+					.await?;
 			}
 		}
 
@@ -177,8 +532,6 @@ impl Database {
 					.bind(socket.port() as i32)
 					.bind(mods.id)
 					.bind(mods.version)
-					.bind(timestamp)
-					.bind(timestamp)
 					.execute(&self.pool)
 					.await?;
 			}
@@ -186,4 +539,201 @@ impl Database {
 
 		Ok(())
 	}
+
+	/// Stores a sighting of a tracked player and notifies webhooks about it
+	async fn player_seen(
+		&self,
+		socket: SocketAddrV4,
+		name: &str,
+		uuid: Option<Uuid>,
+		timestamp: i32,
+	) -> Result<(), sqlx::Error> {
+		match uuid {
+			Some(uuid) => info!("[{socket}] Tracked player {name} ({uuid}) was seen"),
+			None => info!("[{socket}] Tracked player {name} was seen in the query player list"),
+		}
+
+		sqlx::query("INSERT INTO player_sightings (address, port, uuid, name, seen_at) VALUES ($1, $2, $3, $4, $5)")
+			.bind(IpNet::from(Ipv4Net::from(*socket.ip())))
+			.bind(socket.port() as i32)
+			.bind(uuid)
+			.bind(name)
+			.bind(timestamp)
+			.execute(&self.pool)
+			.await?;
+
+		self.notifier.send(Event::PlayerSeen {
+			server: socket.to_string(),
+			name: name.to_string(),
+			uuid: uuid.map(|uuid| uuid.to_string()),
+		});
+
+		Ok(())
+	}
+
+	/// Records a failed ping of a server that's already in the database
+	pub async fn record_failure(
+		&self,
+		socket: SocketAddrV4,
+		error: &RunError,
+	) -> Result<PgQueryResult, sqlx::Error> {
+		let address = IpNet::from(Ipv4Net::from(*socket.ip()));
+		let timestamp = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.unwrap_or_default()
+			.as_secs() as i32;
+
+		// The wait before the next rescan doubles with every failure in a row, but is never less than
+		// a quarter of the time since the server was last seen, so long dead servers are left alone
+		sqlx::query(
+			"UPDATE servers SET
+			consecutive_failures = consecutive_failures + 1,
+			failure_rate = failure_rate * (1 - $8::float8) + $8::float8,
+			last_failure_reason = $3,
+			is_online = consecutive_failures + 1 < $4,
+			next_scan = $5 + LEAST($7, GREATEST(
+				$6 * POWER(2, LEAST(consecutive_failures, 20)),
+				($5 - last_seen) / 4))::integer
+			WHERE address = $1 AND port = $2",
+		)
+		.bind(address)
+		.bind(socket.port() as i32)
+		.bind(error.failure_reason())
+		.bind(self.pruning.offline_after_failures)
+		.bind(timestamp)
+		.bind(self.rescan.min_interval as i32)
+		.bind(self.rescan.max_interval as i32)
+		.bind(FAILURE_WEIGHT)
+		.execute(&self.pool)
+		.await
+	}
+
+	/// Looks for the exact same response on other ports of the address, then on
+	/// other addresses in the same /24. Servers it's repeated on are flagged as well
+	async fn check_repeated_response(
+		&self,
+		socket: SocketAddrV4,
+		server: &Server,
+		description: Option<&str>,
+	) -> Result<Option<HoneypotReason>, sqlx::Error> {
+		// Fresh servers on a shared host all look the same, whether they share an address or a subnet
+		if honeypot::DEFAULT_DESCRIPTIONS.contains(&description.unwrap_or_default()) {
+			return Ok(None);
+		}
+
+		let address = IpNet::from(Ipv4Net::from(*socket.ip()));
+		let subnet = IpNet::from(Ipv4Net::new_assert(*socket.ip(), 24).trunc());
+
+		let (same_address, same_subnet): (i64, i64) = sqlx::query_as(&format!(
+			"SELECT
+			COUNT(*) FILTER (WHERE address = $7 AND port <> $8),
+			COUNT(DISTINCT address) FILTER (WHERE address <> $7)
+			FROM servers WHERE {SAME_RESPONSE}",
+		))
+		.bind(subnet)
+		.bind(description)
+		.bind(&server.version.name)
+		.bind(server.version.protocol)
+		.bind(server.players.online)
+		.bind(server.players.max)
+		.bind(address)
+		.bind(socket.port() as i32)
+		.fetch_one(&self.pool)
+		.await?;
+
+		let (network, reason) = if same_address >= honeypot::MAX_SAME_RESPONSE_PORTS {
+			(address, HoneypotReason::SameResponseOnManyPorts)
+		} else if same_subnet >= honeypot::MAX_SAME_RESPONSE_SUBNET {
+			(subnet, HoneypotReason::SameResponseAcrossSubnet)
+		} else {
+			return Ok(None);
+		};
+
+		sqlx::query(&format!(
+			"UPDATE servers SET suspected_honeypot = $7 WHERE {SAME_RESPONSE}"
+		))
+		.bind(network)
+		.bind(description)
+		.bind(&server.version.name)
+		.bind(server.version.protocol)
+		.bind(server.players.online)
+		.bind(server.players.max)
+		.bind(reason)
+		.execute(&self.pool)
+		.await?;
+
+		Ok(Some(reason))
+	}
+
+	/// Updates a single Bedrock Edition server in the database. Will also remove
+	/// the server from the database if it has requested to be removed
+	pub async fn update_bedrock_server(
+		&self,
+		server: BedrockServer,
+		socket: SocketAddrV4,
+	) -> anyhow::Result<()> {
+		let address = IpNet::from(Ipv4Net::from(*socket.ip()));
+		let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i32;
+
+		// Delete server if it's opted out
+		if server.check_opt_out() {
+			info!("[{}] Bedrock server opted out of scanning", socket);
+			self.opt_out(true, address, socket.port(), timestamp)
+				.await?;
+			Err(RunError::ServerOptOut)?
+		}
+
+		let address_information = self.get_country_info(&address).await?;
+
+		sqlx::query(
+			"INSERT INTO bedrock_servers (
+		address,
+		port,
+		edition,
+		version,
+		protocol,
+		description,
+		level_name,
+		gamemode,
+		server_id,
+		first_seen,
+		last_seen,
+		online_players,
+		max_players,
+		country,
+		asn) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15)
+		ON CONFLICT (address, port) DO UPDATE SET
+		edition = EXCLUDED.edition,
+		version = EXCLUDED.version,
+		protocol = EXCLUDED.protocol,
+		description = EXCLUDED.description,
+		level_name = EXCLUDED.level_name,
+		gamemode = EXCLUDED.gamemode,
+		server_id = EXCLUDED.server_id,
+		last_seen = EXCLUDED.last_seen,
+		online_players = EXCLUDED.online_players,
+		max_players = EXCLUDED.max_players,
+		country = EXCLUDED.country,
+		asn = EXCLUDED.asn",
+		)
+		.bind(address)
+		.bind(socket.port() as i32)
+		.bind(server.edition)
+		.bind(server.version)
+		.bind(server.protocol)
+		.bind(server.description)
+		.bind(server.level_name)
+		.bind(server.gamemode)
+		.bind(server.server_id)
+		.bind(timestamp)
+		.bind(timestamp)
+		.bind(server.online_players)
+		.bind(server.max_players)
+		.bind(address_information.country)
+		.bind(address_information.asn)
+		.execute(&self.pool)
+		.await?;
+
+		Ok(())
+	}
 }
@@ -0,0 +1,205 @@
+use sqlx::types::ipnet::Ipv4Net;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::net::{Ipv4Addr, SocketAddrV4};
+
+// Enough to make neighbouring indexes land far away from each other
+const FEISTEL_ROUNDS: usize = 4;
+
+/// Every address and port in the configured ranges, visited in a random order so
+/// consecutive connections are spread out instead of hammering one subnet
+#[derive(Debug)]
+pub struct Targets {
+	ranges: Vec<Ipv4Net>,
+	ports: Vec<u16>,
+	len: u64,
+	permutation: Permutation,
+}
+
+impl Targets {
+	pub fn new(ranges: Vec<Ipv4Net>, ports: Vec<u16>) -> Targets {
+		let addresses: u64 = ranges.iter().map(range_size).sum();
+		let len = addresses * ports.len() as u64;
+
+		Targets {
+			ranges,
+			ports,
+			len,
+			permutation: Permutation::new(len),
+		}
+	}
+
+	pub fn len(&self) -> u64 {
+		self.len
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.len == 0
+	}
+
+	/// The target at `index` in the random order, every index below `len` gives a different target
+	pub fn get(&self, index: u64) -> SocketAddrV4 {
+		// The permutation covers a power of two, anything past the end is
+		// permuted again until it lands inside
+		let mut n = self.permutation.apply(index);
+		while n >= self.len {
+			n = self.permutation.apply(n);
+		}
+
+		let port = self.ports[(n % self.ports.len() as u64) as usize];
+		let mut offset = n / self.ports.len() as u64;
+
+		for range in &self.ranges {
+			let size = range_size(range);
+			if offset < size {
+				let address = range.network().to_bits() + offset as u32;
+				return SocketAddrV4::new(Ipv4Addr::from_bits(address), port);
+			}
+
+			offset -= size;
+		}
+
+		unreachable!("index is within the length of the targets")
+	}
+
+	pub fn iter(&self) -> impl Iterator<Item = SocketAddrV4> + '_ {
+		(0..self.len).map(|i| self.get(i))
+	}
+}
+
+/// Parses a range from the config, either in CIDR notation or a single address
+pub fn parse_range(range: &str) -> Option<Ipv4Net> {
+	let range = range.trim();
+
+	range
+		.parse::<Ipv4Net>()
+		.or_else(|_| range.parse::<Ipv4Addr>().map(Ipv4Net::from))
+		.map(|net| net.trunc())
+		.ok()
+}
+
+fn range_size(range: &Ipv4Net) -> u64 {
+	1 << (32 - range.prefix_len())
+}
+
+/// A Feistel network over the smallest even number of bits that fits every
+/// target, with new random keys each scan
+#[derive(Debug)]
+struct Permutation {
+	half_bits: u32,
+	keys: [u64; FEISTEL_ROUNDS],
+}
+
+impl Permutation {
+	fn new(len: u64) -> Permutation {
+		let bits = u64::BITS - len.saturating_sub(1).leading_zeros();
+		let random = RandomState::new();
+
+		Permutation {
+			half_bits: bits.div_ceil(2).max(1),
+			keys: std::array::from_fn(|i| random.hash_one(i)),
+		}
+	}
+
+	fn apply(&self, n: u64) -> u64 {
+		let mask = (1 << self.half_bits) - 1;
+		let (mut left, mut right) = (n >> self.half_bits, n & mask);
+
+		for key in self.keys {
+			(left, right) = (right, left ^ (mix(right ^ key) & mask));
+		}
+
+		(left << self.half_bits) | right
+	}
+}
+
+// The splitmix64 finalizer
+fn mix(mut x: u64) -> u64 {
+	x ^= x >> 30;
+	x = x.wrapping_mul(0xbf58476d1ce4e5b9);
+	x ^= x >> 27;
+	x = x.wrapping_mul(0x94d049bb133111eb);
+	x ^ (x >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::collections::HashSet;
+
+	fn targets(ranges: &[&str], ports: &[u16]) -> Targets {
+		let ranges = ranges.iter().map(|r| parse_range(r).unwrap()).collect();
+		Targets::new(ranges, ports.to_vec())
+	}
+
+	// Every address in the ranges with every port
+	fn expected(targets: &Targets) -> HashSet<SocketAddrV4> {
+		let mut expected = HashSet::new();
+
+		for range in &targets.ranges {
+			for address in range.hosts().chain([range.network(), range.broadcast()]) {
+				for port in &targets.ports {
+					expected.insert(SocketAddrV4::new(address, *port));
+				}
+			}
+		}
+
+		expected
+	}
+
+	#[test]
+	fn visits_every_target_once() {
+		let cases: &[(&[&str], &[u16])] = &[
+			// Only one target, zero bits
+			(&["192.0.2.1"], &[25565]),
+			(&["192.0.2.1/32"], &[25565, 25566, 25567]),
+			// 18 targets, an odd number of bits
+			(&["192.0.2.8/29", "198.51.100.7"], &[25565, 25566]),
+			(&["192.0.2.0/30", "192.0.2.128/31"], &[25565, 25566, 25567]),
+			// 112 targets, 7 bits
+			(&["198.51.100.16/28"], &[1, 2, 3, 4, 5, 6, 7]),
+			(&["203.0.113.0/24"], &[25565]),
+			(
+				&["10.0.0.0/23", "10.1.0.0/25"],
+				&[25565, 25575, 25585, 25595, 25605],
+			),
+		];
+
+		for (ranges, ports) in cases {
+			let targets = targets(ranges, ports);
+			let visited: Vec<SocketAddrV4> = targets.iter().collect();
+			let unique: HashSet<SocketAddrV4> = visited.iter().copied().collect();
+
+			assert_eq!(visited.len() as u64, targets.len(), "{ranges:?} {ports:?}");
+			assert_eq!(unique.len(), visited.len(), "{ranges:?} {ports:?}");
+			assert_eq!(unique, expected(&targets), "{ranges:?} {ports:?}");
+		}
+	}
+
+	#[test]
+	fn empty_targets() {
+		assert!(targets(&[], &[25565]).is_empty());
+		assert!(targets(&["192.0.2.0/24"], &[]).is_empty());
+		assert_eq!(targets(&[], &[]).iter().count(), 0);
+	}
+
+	#[test]
+	fn permutation_is_a_bijection() {
+		for len in [1, 2, 3, 5, 8, 100, 1000, 4097] {
+			let permutation = Permutation::new(len);
+			let size = 1 << (permutation.half_bits * 2);
+			let outputs: HashSet<u64> = (0..size).map(|n| permutation.apply(n)).collect();
+
+			assert_eq!(outputs.len() as u64, size, "{len}");
+			assert!(outputs.iter().all(|n| *n < size), "{len}");
+		}
+	}
+
+	#[test]
+	fn parses_ranges() {
+		assert_eq!(parse_range("192.0.2.7/24"), "192.0.2.0/24".parse().ok());
+		assert_eq!(parse_range(" 192.0.2.7 "), "192.0.2.7/32".parse().ok());
+		assert_eq!(parse_range("192.0.2.0/33"), None);
+		assert_eq!(parse_range("example.com"), None);
+	}
+}
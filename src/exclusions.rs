@@ -0,0 +1,383 @@
+use crate::config::Exclusions as ExclusionsConfig;
+use crate::discovery;
+use sqlx::types::ipnet::{IpNet, Ipv4Net};
+use sqlx::PgPool;
+use std::collections::HashSet;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+// Never scanned unless default_exclusions is turned off
+#[rustfmt::skip]
+const DEFAULT_EXCLUSIONS: &[&str] = &[
+	// "This network", private networks, shared address space (CGNAT) and loopback
+	"0.0.0.0/8", "10.0.0.0/8", "100.64.0.0/10", "127.0.0.0/8", "172.16.0.0/12", "192.168.0.0/16",
+	// Link local, IETF protocol assignments, documentation and benchmarking
+	"169.254.0.0/16", "192.0.0.0/24", "192.0.2.0/24", "198.18.0.0/15", "198.51.100.0/24", "203.0.113.0/24",
+	// Multicast, reserved and broadcast
+	"224.0.0.0/4", "240.0.0.0/4", "255.255.255.255/32",
+	// US Department of Defense
+	"6.0.0.0/8", "7.0.0.0/8", "11.0.0.0/8", "21.0.0.0/8", "22.0.0.0/8", "26.0.0.0/8", "28.0.0.0/8",
+	"29.0.0.0/8", "30.0.0.0/8", "33.0.0.0/8", "55.0.0.0/8", "214.0.0.0/8", "215.0.0.0/8",
+];
+
+// Everything stored about a server, removed when its owner asks to be excluded
+const PURGE_TABLES: &[&str] = &[
+	"servers",
+	"bedrock_servers",
+	"players",
+	"query_players",
+	"player_sightings",
+	"plugins",
+	"mods",
+	"server_snapshots",
+	"server_changes",
+];
+
+/// Networks and opted out servers that are never scanned in any mode
+#[derive(Debug, Default)]
+pub struct Exclusions {
+	// Sorted, non overlapping inclusive ranges of addresses
+	ranges: Vec<(u32, u32)>,
+	opt_outs: HashSet<SocketAddrV4>,
+	bedrock_opt_outs: HashSet<SocketAddrV4>,
+}
+
+impl Exclusions {
+	/// Loads the default exclusions, the exclude file, the scan_exclusions table and opt outs
+	pub async fn load(config: &ExclusionsConfig, pool: &PgPool) -> anyhow::Result<Exclusions> {
+		let mut networks = configured_networks(config)?;
+
+		let rows: Vec<(IpNet,)> = sqlx::query_as("SELECT network FROM scan_exclusions")
+			.fetch_all(pool)
+			.await?;
+
+		networks.extend(rows.into_iter().filter_map(|(network,)| match network {
+			IpNet::V4(network) => Some(network),
+			IpNet::V6(_) => None,
+		}));
+
+		let mut exclusions = Exclusions::from(networks);
+		exclusions.opt_outs = load_opt_outs(pool, "opt_outs").await?;
+		exclusions.bedrock_opt_outs = load_opt_outs(pool, "bedrock_opt_outs").await?;
+
+		Ok(exclusions)
+	}
+
+	/// Whether a Java Edition server is opted out or in an excluded network
+	pub fn skips(&self, socket: SocketAddrV4) -> bool {
+		self.opt_outs.contains(&socket) || self.contains(*socket.ip())
+	}
+
+	/// Whether a Bedrock Edition server is opted out or in an excluded network
+	pub fn skips_bedrock(&self, socket: SocketAddrV4) -> bool {
+		self.bedrock_opt_outs.contains(&socket) || self.contains(*socket.ip())
+	}
+
+	/// Writes every excluded network as a range, one per line, for masscan's --excludefile.
+	/// Opted out servers are only a single port, so those are still filtered from its output
+	pub fn write_masscan_file(&self, path: &Path) -> std::io::Result<()> {
+		let lines: String = self
+			.ranges
+			.iter()
+			.map(|(start, end)| {
+				format!(
+					"{}-{}\n",
+					Ipv4Addr::from_bits(*start),
+					Ipv4Addr::from_bits(*end)
+				)
+			})
+			.collect();
+
+		std::fs::write(path, lines)
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.ranges.is_empty()
+	}
+
+	/// Every excluded network as an inclusive range of addresses, sorted and without overlaps
+	pub fn ranges(&self) -> &[(u32, u32)] {
+		&self.ranges
+	}
+
+	fn contains(&self, address: Ipv4Addr) -> bool {
+		let address = address.to_bits();
+		let i = self.ranges.partition_point(|(_, end)| *end < address);
+
+		self.ranges
+			.get(i)
+			.is_some_and(|(start, _)| *start <= address)
+	}
+}
+
+impl From<Vec<Ipv4Net>> for Exclusions {
+	fn from(networks: Vec<Ipv4Net>) -> Self {
+		let mut ranges: Vec<(u32, u32)> = networks
+			.iter()
+			.map(|n| (n.network().to_bits(), n.broadcast().to_bits()))
+			.collect();
+		ranges.sort_unstable();
+
+		// Overlapping and adjacent ranges are merged so they can be binary searched
+		let mut merged: Vec<(u32, u32)> = Vec::with_capacity(ranges.len());
+		for (start, end) in ranges {
+			match merged.last_mut() {
+				Some(last) if start <= last.1.saturating_add(1) => last.1 = last.1.max(end),
+				_ => merged.push((start, end)),
+			}
+		}
+
+		Exclusions {
+			ranges: merged,
+			opt_outs: HashSet::new(),
+			bedrock_opt_outs: HashSet::new(),
+		}
+	}
+}
+
+/// Reads the IPv4 opt outs of one edition
+async fn load_opt_outs(pool: &PgPool, table: &str) -> anyhow::Result<HashSet<SocketAddrV4>> {
+	let opt_outs: Vec<(IpNet, i32)> = sqlx::query_as(&format!("SELECT address, port FROM {table}"))
+		.fetch_all(pool)
+		.await?;
+
+	Ok(opt_outs
+		.into_iter()
+		.filter_map(|(address, port)| match address {
+			IpNet::V4(address) => Some(SocketAddrV4::new(address.addr(), port as u16)),
+			IpNet::V6(_) => None,
+		})
+		.collect())
+}
+
+/// The default exclusions if they are turned on and the networks in the exclude file
+pub fn configured_networks(config: &ExclusionsConfig) -> anyhow::Result<Vec<Ipv4Net>> {
+	let mut networks = Vec::new();
+
+	if config.default_exclusions {
+		networks.extend(
+			DEFAULT_EXCLUSIONS
+				.iter()
+				.filter_map(|n| discovery::parse_range(n)),
+		);
+	}
+
+	if let Some(file) = &config.file {
+		let contents = std::fs::read_to_string(file)?;
+
+		// One range per line, everything after a # is a comment
+		for line in contents.lines() {
+			let line = line.split('#').next().unwrap_or_default().trim();
+			if line.is_empty() {
+				continue;
+			}
+
+			match discovery::parse_range(line) {
+				Some(network) => networks.push(network),
+				None => warn!("Invalid range in exclude file: {line}"),
+			}
+		}
+	}
+
+	Ok(networks)
+}
+
+/// Excludes a network from every scan and removes everything stored about it
+pub async fn exclude(pool: &PgPool, network: Ipv4Net, reason: Option<&str>) -> anyhow::Result<u64> {
+	let network = IpNet::from(network);
+	let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i32;
+	let mut transaction = pool.begin().await?;
+
+	sqlx::query(
+		"INSERT INTO scan_exclusions (network, reason, added_at) VALUES ($1, $2, $3)
+		ON CONFLICT (network) DO UPDATE SET reason = EXCLUDED.reason",
+	)
+	.bind(network)
+	.bind(reason)
+	.bind(timestamp)
+	.execute(&mut *transaction)
+	.await?;
+
+	let mut removed = 0;
+	for table in PURGE_TABLES {
+		let result = sqlx::query(&format!("DELETE FROM {table} WHERE address <<= $1"))
+			.bind(network)
+			.execute(&mut *transaction)
+			.await?;
+
+		if *table == "servers" {
+			removed = result.rows_affected();
+		}
+	}
+
+	transaction.commit().await?;
+	Ok(removed)
+}
+
+/// Reverses opt outs of both editions, for every port of the address if no port is given
+pub async fn opt_in(pool: &PgPool, address: Ipv4Addr, port: Option<u16>) -> anyhow::Result<u64> {
+	let mut removed = 0;
+	for table in ["opt_outs", "bedrock_opt_outs"] {
+		let result = sqlx::query(&format!(
+			"DELETE FROM {table} WHERE address = $1 AND ($2::integer IS NULL OR port = $2)"
+		))
+		.bind(IpNet::from(Ipv4Net::from(address)))
+		.bind(port.map(i32::from))
+		.execute(pool)
+		.await?;
+
+		removed += result.rows_affected();
+	}
+
+	Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn exclusions(networks: &[&str]) -> Exclusions {
+		Exclusions::from(
+			networks
+				.iter()
+				.map(|n| discovery::parse_range(n).unwrap())
+				.collect::<Vec<_>>(),
+		)
+	}
+
+	fn bits(address: &str) -> u32 {
+		address.parse::<Ipv4Addr>().unwrap().to_bits()
+	}
+
+	#[test]
+	fn merges_overlapping_and_adjacent_ranges() {
+		let exclusions = exclusions(&[
+			"192.0.2.128/25",
+			"10.0.0.0/8",
+			// Inside 10.0.0.0/8
+			"10.20.0.0/16",
+			// Right after 192.0.2.128/25 ends
+			"192.0.3.0/24",
+			"192.0.2.0/25",
+			"203.0.113.7",
+		]);
+
+		assert_eq!(
+			exclusions.ranges,
+			vec![
+				(bits("10.0.0.0"), bits("10.255.255.255")),
+				(bits("192.0.2.0"), bits("192.0.3.255")),
+				(bits("203.0.113.7"), bits("203.0.113.7")),
+			]
+		);
+	}
+
+	#[test]
+	fn merges_up_to_the_last_address() {
+		let exclusions = exclusions(&["255.255.255.255", "255.255.255.0/24", "0.0.0.0/0"]);
+		assert_eq!(exclusions.ranges, vec![(0, u32::MAX)]);
+	}
+
+	#[test]
+	fn contains_range_edges() {
+		let exclusions = exclusions(&["10.0.0.0/8", "192.0.2.0/24", "203.0.113.7"]);
+
+		for address in [
+			"10.0.0.0",
+			"10.255.255.255",
+			"192.0.2.0",
+			"192.0.2.255",
+			"203.0.113.7",
+		] {
+			assert!(exclusions.contains(address.parse().unwrap()), "{address}");
+		}
+
+		for address in [
+			"9.255.255.255",
+			"11.0.0.0",
+			"192.0.1.255",
+			"192.0.3.0",
+			"203.0.113.6",
+			"203.0.113.8",
+			"0.0.0.0",
+			"255.255.255.255",
+		] {
+			assert!(!exclusions.contains(address.parse().unwrap()), "{address}");
+		}
+	}
+
+	#[test]
+	fn contains_nothing_when_empty() {
+		let exclusions = exclusions(&[]);
+
+		assert!(exclusions.is_empty());
+		assert!(!exclusions.contains(Ipv4Addr::UNSPECIFIED));
+		assert!(!exclusions.contains(Ipv4Addr::BROADCAST));
+	}
+
+	#[test]
+	fn skips_opted_out_ports_only() {
+		let mut exclusions = exclusions(&["10.0.0.0/8"]);
+		let opted_out = SocketAddrV4::new(Ipv4Addr::new(192, 0, 2, 1), 25565);
+		exclusions.opt_outs.insert(opted_out);
+
+		assert!(exclusions.skips(opted_out));
+		assert!(exclusions.skips(SocketAddrV4::new(Ipv4Addr::new(10, 1, 2, 3), 25565)));
+		assert!(!exclusions.skips(SocketAddrV4::new(Ipv4Addr::new(192, 0, 2, 1), 25566)));
+	}
+
+	#[test]
+	fn opt_outs_only_cover_their_edition() {
+		let mut exclusions = exclusions(&["10.0.0.0/8"]);
+		let java = SocketAddrV4::new(Ipv4Addr::new(192, 0, 2, 1), 25565);
+		let bedrock = SocketAddrV4::new(Ipv4Addr::new(192, 0, 2, 1), 19132);
+		exclusions.opt_outs.insert(java);
+		exclusions.bedrock_opt_outs.insert(bedrock);
+
+		assert!(exclusions.skips(java));
+		assert!(!exclusions.skips_bedrock(java));
+		assert!(exclusions.skips_bedrock(bedrock));
+		assert!(!exclusions.skips(bedrock));
+
+		// Excluded networks are skipped for both
+		let excluded = SocketAddrV4::new(Ipv4Addr::new(10, 1, 2, 3), 19132);
+		assert!(exclusions.skips(excluded));
+		assert!(exclusions.skips_bedrock(excluded));
+	}
+
+	#[test]
+	fn default_exclusions_cover_private_networks() {
+		let config = ExclusionsConfig::default();
+		let exclusions = Exclusions::from(configured_networks(&config).unwrap());
+
+		for address in [
+			"127.0.0.1",
+			"10.1.2.3",
+			"192.168.1.1",
+			"172.31.255.255",
+			"6.6.6.6",
+		] {
+			assert!(exclusions.contains(address.parse().unwrap()), "{address}");
+		}
+
+		assert!(!exclusions.contains(Ipv4Addr::new(1, 1, 1, 1)));
+	}
+
+	#[test]
+	fn writes_masscan_ranges() {
+		let path = std::env::temp_dir().join(format!("exclusions-test-{}.txt", std::process::id()));
+		exclusions(&["192.0.2.0/24", "10.0.0.0/8", "203.0.113.7"])
+			.write_masscan_file(&path)
+			.unwrap();
+
+		let written = std::fs::read_to_string(&path).unwrap();
+		std::fs::remove_file(&path).unwrap();
+
+		assert_eq!(
+			written,
+			"10.0.0.0-10.255.255.255\n192.0.2.0-192.0.2.255\n203.0.113.7-203.0.113.7\n"
+		);
+	}
+}
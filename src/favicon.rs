@@ -0,0 +1,139 @@
+use base64::prelude::{Engine, BASE64_STANDARD};
+use sha2::{Digest, Sha256};
+use tracing::debug;
+
+// Favicons are sent as a data URI of a base64 encoded PNG
+const DATA_URI_PREFIX: &str = "data:image/png;base64,";
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+// The client only accepts favicons of exactly this size
+const FAVICON_SIZE: u32 = 64;
+// Signature, chunk length, chunk type, then the width and height from IHDR
+const PNG_HEADER_SIZE: usize = 8 + 4 + 4 + 4 + 4;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Favicon {
+	// Hex encoded SHA-256 of the PNG
+	pub hash: String,
+	pub png: Vec<u8>,
+}
+
+impl Favicon {
+	/// Decodes a favicon from a status response, only 64x64 PNGs are accepted
+	pub fn parse(uri: &str) -> Option<Favicon> {
+		// Some servers send line breaks in the base64, the client ignores these
+		let base64: String = uri
+			.strip_prefix(DATA_URI_PREFIX)?
+			.chars()
+			.filter(|c| !c.is_ascii_whitespace())
+			.collect();
+
+		let png = match BASE64_STANDARD.decode(base64) {
+			Ok(png) => png,
+			Err(e) => {
+				debug!("Favicon isn't valid base64: {e}");
+				return None;
+			}
+		};
+
+		if png.len() < PNG_HEADER_SIZE || png[..8] != PNG_SIGNATURE || &png[12..16] != b"IHDR" {
+			debug!("Favicon isn't a PNG");
+			return None;
+		}
+
+		let width = u32::from_be_bytes([png[16], png[17], png[18], png[19]]);
+		let height = u32::from_be_bytes([png[20], png[21], png[22], png[23]]);
+
+		if width != FAVICON_SIZE || height != FAVICON_SIZE {
+			debug!("Favicon is {width}x{height} instead of 64x64");
+			return None;
+		}
+
+		let hash = Sha256::digest(&png)
+			.iter()
+			.map(|b| format!("{b:02x}"))
+			.collect();
+
+		Some(Favicon { hash, png })
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// Start of a PNG up to and including the IHDR chunk, nothing after it is read
+	fn png(width: u32, height: u32) -> Vec<u8> {
+		let mut png = Vec::from(PNG_SIGNATURE);
+		png.extend_from_slice(&13_u32.to_be_bytes());
+		png.extend_from_slice(b"IHDR");
+		png.extend_from_slice(&width.to_be_bytes());
+		png.extend_from_slice(&height.to_be_bytes());
+		// Bit depth, RGBA, compression, filter, interlace and the CRC
+		png.extend_from_slice(&[8, 6, 0, 0, 0, 0, 0, 0, 0]);
+		png
+	}
+
+	fn uri(png: &[u8]) -> String {
+		format!("{DATA_URI_PREFIX}{}", BASE64_STANDARD.encode(png))
+	}
+
+	#[test]
+	fn parses_64x64_png() {
+		let favicon = Favicon::parse(&uri(&png(64, 64))).unwrap();
+
+		assert_eq!(favicon.png, png(64, 64));
+		assert_eq!(
+			favicon.hash,
+			"8630ef506bae1247c5f7746f441675e49fff2c6a789f220275cbddfa0d276abb"
+		);
+	}
+
+	#[test]
+	fn ignores_line_breaks() {
+		let uri = "data:image/png;base64,iVBORw0KGgoAAAANSUhE\nUgAAAEAAAABACAYAAAAA\r\nAAAA";
+		assert_eq!(Favicon::parse(uri).unwrap().png, png(64, 64));
+	}
+
+	#[test]
+	fn rejects_other_sizes() {
+		for (width, height) in [(128, 128), (64, 63), (32, 64), (0, 0)] {
+			assert_eq!(Favicon::parse(&uri(&png(width, height))), None);
+		}
+	}
+
+	#[test]
+	fn rejects_other_images() {
+		let mut jpeg = png(64, 64);
+		jpeg[..4].copy_from_slice(&[0xFF, 0xD8, 0xFF, 0xE0]);
+		assert_eq!(Favicon::parse(&uri(&jpeg)), None);
+
+		// A PNG that doesn't start with the header chunk
+		let mut png = png(64, 64);
+		png[12..16].copy_from_slice(b"tEXt");
+		assert_eq!(Favicon::parse(&uri(&png)), None);
+
+		// Cut off before the height
+		assert_eq!(Favicon::parse(&uri(&png[..20])), None);
+	}
+
+	#[test]
+	fn rejects_missing_prefix() {
+		let base64 = BASE64_STANDARD.encode(png(64, 64));
+
+		assert_eq!(Favicon::parse(&base64), None);
+		assert_eq!(
+			Favicon::parse(&format!("data:image/jpeg;base64,{base64}")),
+			None
+		);
+	}
+
+	#[test]
+	fn rejects_invalid_base64() {
+		assert_eq!(
+			Favicon::parse("data:image/png;base64,iVBORw0KGgo*AAAA"),
+			None
+		);
+		assert_eq!(Favicon::parse("data:image/png;base64,iVBORw0KGgoAAA"), None);
+		assert_eq!(Favicon::parse(DATA_URI_PREFIX), None);
+	}
+}
@@ -0,0 +1,105 @@
+use crate::config::History;
+use md5::{Digest, Md5};
+use sqlx::{FromRow, PgPool};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+// How often old snapshots and changes are thinned out and deleted
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const DAY: u64 = 60 * 60 * 24;
+
+/// The fields of a server that are kept in the change log
+#[derive(Debug, Clone, Default, PartialEq, FromRow)]
+pub struct TrackedFields {
+	pub software: Option<String>,
+	pub version: Option<String>,
+	pub motd_hash: Option<String>,
+	pub icon_hash: Option<String>,
+}
+
+/// A field that is different to what was stored for a server
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+	pub field: &'static str,
+	pub old: Option<String>,
+	pub new: Option<String>,
+}
+
+impl TrackedFields {
+	pub fn changes(&self, new: &TrackedFields) -> Vec<Change> {
+		[
+			("software", &self.software, &new.software),
+			("version", &self.version, &new.version),
+			("motd_hash", &self.motd_hash, &new.motd_hash),
+			("icon_hash", &self.icon_hash, &new.icon_hash),
+		]
+		.into_iter()
+		.filter(|(_, old, new)| old != new)
+		.map(|(field, old, new)| Change {
+			field,
+			old: old.clone(),
+			new: new.clone(),
+		})
+		.collect()
+	}
+}
+
+/// Hash of a formatted description, the same as md5(description_formatted) in Postgres
+pub fn motd_hash(formatted: &str) -> String {
+	Md5::digest(formatted)
+		.iter()
+		.map(|b| format!("{b:02x}"))
+		.collect()
+}
+
+/// Thins out and deletes old history every hour, forever
+pub async fn prune(pool: PgPool, config: History) {
+	loop {
+		if let Err(e) = prune_once(&pool, &config).await {
+			warn!("Failed to prune server history: {e}");
+		}
+
+		tokio::time::sleep(PRUNE_INTERVAL).await;
+	}
+}
+
+async fn prune_once(pool: &PgPool, config: &History) -> anyhow::Result<()> {
+	let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+	let days_ago = |days: u64| now.saturating_sub(days * DAY) as i32;
+
+	// Only the busiest snapshot of each day is kept for each server
+	if config.downsample_after_days > 0 {
+		let result = sqlx::query(
+			"DELETE FROM server_snapshots s WHERE taken_at < $1 AND EXISTS (
+			SELECT 1 FROM server_snapshots o
+			WHERE o.address = s.address AND o.port = s.port AND o.taken_at / 86400 = s.taken_at / 86400
+			AND (COALESCE(o.online_players, -1) > COALESCE(s.online_players, -1)
+				OR (COALESCE(o.online_players, -1) = COALESCE(s.online_players, -1) AND o.taken_at < s.taken_at)))",
+		)
+		.bind(days_ago(config.downsample_after_days))
+		.execute(pool)
+		.await?;
+
+		info!("Downsampled {} server snapshots", result.rows_affected());
+	}
+
+	if config.retention_days > 0 {
+		let result = sqlx::query("DELETE FROM server_snapshots WHERE taken_at < $1")
+			.bind(days_ago(config.retention_days))
+			.execute(pool)
+			.await?;
+
+		info!("Deleted {} old server snapshots", result.rows_affected());
+	}
+
+	if config.change_retention_days > 0 {
+		let result = sqlx::query("DELETE FROM server_changes WHERE changed_at < $1")
+			.bind(days_ago(config.change_retention_days))
+			.execute(pool)
+			.await?;
+
+		info!("Deleted {} old server changes", result.rows_affected());
+	}
+
+	Ok(())
+}
@@ -0,0 +1,175 @@
+use crate::response::{PlayerKind, Server};
+
+// Other ports on the same address giving the exact same response before it's suspicious
+pub const MAX_SAME_RESPONSE_PORTS: i64 = 5;
+// Other addresses in the same /24 giving the exact same response before it's suspicious
+pub const MAX_SAME_RESPONSE_SUBNET: i64 = 10;
+// Hosting providers often have a whole /24 or many ports of one address with fresh
+// servers with these, so they aren't used to compare responses between servers
+pub const DEFAULT_DESCRIPTIONS: &[&str] = &["", "A Minecraft Server", "A Velocity Server"];
+// No real server has ever had this many players online
+const MAX_REAL_PLAYERS: i32 = 1_000_000;
+
+/// Why a server is suspected to be a honeypot or a fake server
+#[derive(Debug, Clone, Copy, PartialEq, sqlx::Type)]
+#[sqlx(type_name = "honeypot_reason", rename_all = "snake_case")]
+pub enum HoneypotReason {
+	// Negative player counts, or far more players online than the server allows
+	ImpossiblePlayerCount,
+	// Player names in the sample with UUIDs no Minecraft server would give out
+	FakePlayerSample,
+	// The same response was given on many ports of one address
+	SameResponseOnManyPorts,
+	// The same response was given by many addresses in one /24
+	SameResponseAcrossSubnet,
+}
+
+/// Checks the parts of a response that can't come from a real server. Checks that
+/// need other servers to compare against are done in `Database::update_server`
+pub fn check_response(server: &Server) -> Option<HoneypotReason> {
+	let players = &server.players;
+
+	if players.online < 0
+		|| players.max < 0
+		|| players.online > MAX_REAL_PLAYERS
+		|| (players.online > 1000 && players.online / 100 > players.max)
+	{
+		return Some(HoneypotReason::ImpossiblePlayerCount);
+	}
+
+	// Text lines in the sample use the nil UUID too, they are left alone as long as
+	// they don't look like player names
+	if let Some(sample) = &players.sample {
+		let fake = sample
+			.iter()
+			.filter(|p| is_player_name(&p.name) && p.kind() == PlayerKind::Text)
+			.count();
+
+		if fake >= 2 && fake * 2 >= sample.len() {
+			return Some(HoneypotReason::FakePlayerSample);
+		}
+	}
+
+	None
+}
+
+// Names a Minecraft account can have
+fn is_player_name(name: &str) -> bool {
+	(3..=16).contains(&name.len()) && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::response::Player;
+
+	// Notch, given out by Mojang
+	const ONLINE_UUID: &str = "069a79f4-44e9-4726-a5be-fca90e38aaf5";
+	const NIL_UUID: &str = "00000000-0000-0000-0000-000000000000";
+
+	fn server(online: i32, max: i32, sample: Option<Vec<Player>>) -> Server {
+		let mut server =
+			Server::from_legacy("§1\x00767\x001.21.4\x00A Minecraft Server\x000\x0020").unwrap();
+		server.players.online = online;
+		server.players.max = max;
+		server.players.sample = sample;
+		server
+	}
+
+	fn player(name: &str, id: &str) -> Player {
+		Player {
+			id: id.to_string(),
+			name: name.to_string(),
+		}
+	}
+
+	#[test]
+	fn vanilla_response_is_not_flagged() {
+		let sample = vec![
+			player("Notch", ONLINE_UUID),
+			player("jeb_", "853c80ef-3c37-49fd-aa49-938b674adae6"),
+		];
+
+		assert_eq!(check_response(&server(0, 20, None)), None);
+		assert_eq!(check_response(&server(2, 20, Some(sample))), None);
+		// Full servers and ones over their limit happen, e.g. with a slot bypass permission
+		assert_eq!(check_response(&server(25, 20, None)), None);
+	}
+
+	#[test]
+	fn flags_negative_player_counts() {
+		assert_eq!(
+			check_response(&server(-1, 20, None)),
+			Some(HoneypotReason::ImpossiblePlayerCount)
+		);
+		assert_eq!(
+			check_response(&server(0, -1, None)),
+			Some(HoneypotReason::ImpossiblePlayerCount)
+		);
+	}
+
+	#[test]
+	fn flags_more_players_than_have_ever_been_online() {
+		assert_eq!(
+			check_response(&server(MAX_REAL_PLAYERS, i32::MAX, None)),
+			None
+		);
+		assert_eq!(
+			check_response(&server(MAX_REAL_PLAYERS + 1, i32::MAX, None)),
+			Some(HoneypotReason::ImpossiblePlayerCount)
+		);
+	}
+
+	#[test]
+	fn flags_far_more_players_than_allowed() {
+		// Up to a hundred times the max is allowed, and anything up to a thousand players
+		assert_eq!(check_response(&server(1000, 1, None)), None);
+		assert_eq!(check_response(&server(1099, 10, None)), None);
+		assert_eq!(
+			check_response(&server(1100, 10, None)),
+			Some(HoneypotReason::ImpossiblePlayerCount)
+		);
+		assert_eq!(
+			check_response(&server(1001, 0, None)),
+			Some(HoneypotReason::ImpossiblePlayerCount)
+		);
+	}
+
+	#[test]
+	fn flags_sample_of_fake_players() {
+		let fake = |name: &str| player(name, NIL_UUID);
+
+		// A single one could be a plugin's text line that happens to look like a name
+		let sample = vec![fake("Steve"), player("Notch", ONLINE_UUID)];
+		assert_eq!(check_response(&server(2, 20, Some(sample))), None);
+
+		// Two fake players make up half the sample
+		let sample = vec![
+			fake("Steve"),
+			fake("Alex"),
+			player("Notch", ONLINE_UUID),
+			player("Dinnerbone", "61699b2e-d327-4a01-9f1e-0ea8c3f06bc6"),
+		];
+		assert_eq!(
+			check_response(&server(4, 20, Some(sample.clone()))),
+			Some(HoneypotReason::FakePlayerSample)
+		);
+
+		// Less than half the sample
+		let mut larger = sample;
+		larger.push(player("jeb_", "853c80ef-3c37-49fd-aa49-938b674adae6"));
+		assert_eq!(check_response(&server(5, 20, Some(larger))), None);
+	}
+
+	#[test]
+	fn text_lines_are_not_fake_players() {
+		let sample = vec![
+			player("§6Welcome to the server!", NIL_UUID),
+			player("Join our Discord", NIL_UUID),
+			player("Anonymous Player", NIL_UUID),
+			player("anonymous_players_too_long", NIL_UUID),
+		];
+
+		assert_eq!(check_response(&server(4, 20, Some(sample))), None);
+	}
+}
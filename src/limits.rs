@@ -0,0 +1,440 @@
+use crate::config::{load_config, ScannerConfig};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddrV4;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{Notify, OwnedSemaphorePermit, Semaphore};
+use tracing::{error, info, warn};
+
+// Targets set aside for busy subnets before the dispatcher stops taking new ones
+const MAX_DEFERRED: usize = 10_000;
+
+/// How many servers a scanner pings at once and how long it waits for them.
+/// Every value can be changed while scanning, see [`reload_on_hangup`]
+#[derive(Debug)]
+pub struct Limits {
+	permits: Arc<Semaphore>,
+	max_in_flight: AtomicUsize,
+	// Permits still to be taken away after max_in_flight was lowered, as tasks holding them finish
+	owed_permits: AtomicUsize,
+	max_per_subnet: AtomicUsize,
+	connect_timeout_ms: AtomicU64,
+	read_timeout_ms: AtomicU64,
+	in_flight: AtomicUsize,
+	// Running tasks in each /24, keyed by the first three octets
+	subnets: Mutex<HashMap<u32, usize>>,
+	// Changes every time a subnet may have room for another task
+	subnet_generation: AtomicU64,
+	subnet_freed: Notify,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Timeouts {
+	pub connect: Duration,
+	pub read: Duration,
+}
+
+/// Held by a task for as long as it is pinging a server
+#[derive(Debug)]
+pub struct Permit {
+	// Taken when the permit is forgotten instead of going back to the semaphore
+	permit: Option<OwnedSemaphorePermit>,
+	slot: SubnetSlot,
+}
+
+// Released on its own so a cancelled acquire doesn't leave the subnet counted
+#[derive(Debug)]
+struct SubnetSlot {
+	limits: Arc<Limits>,
+	subnet: u32,
+}
+
+/// Starts a task for each target in the order they are pushed, as soon as the limits allow.
+/// Targets in a /24 that's at max_per_subnet are set aside until it has room, so they
+/// don't hold up the targets in other subnets behind them
+pub struct Dispatcher<F> {
+	limits: Arc<Limits>,
+	spawn: F,
+	deferred: HashMap<u32, VecDeque<SocketAddrV4>>,
+	deferred_len: usize,
+	// Subnet generation the deferred targets were last checked at
+	generation: u64,
+}
+
+impl Limits {
+	pub fn new(config: &ScannerConfig) -> Limits {
+		let max_in_flight = config.max_in_flight.max(1);
+
+		Limits {
+			permits: Arc::new(Semaphore::new(max_in_flight)),
+			max_in_flight: AtomicUsize::new(max_in_flight),
+			owed_permits: AtomicUsize::new(0),
+			max_per_subnet: AtomicUsize::new(config.max_per_subnet),
+			connect_timeout_ms: AtomicU64::new(config.connect_timeout_ms),
+			read_timeout_ms: AtomicU64::new(config.read_timeout_ms),
+			in_flight: AtomicUsize::new(0),
+			subnets: Mutex::new(HashMap::new()),
+			subnet_generation: AtomicU64::new(0),
+			subnet_freed: Notify::new(),
+		}
+	}
+
+	/// Creates a dispatcher that calls `spawn` with each target and the permit its task has to hold
+	pub fn dispatcher<F: FnMut(SocketAddrV4, Permit)>(self: &Arc<Self>, spawn: F) -> Dispatcher<F> {
+		Dispatcher {
+			limits: self.clone(),
+			spawn,
+			deferred: HashMap::new(),
+			deferred_len: 0,
+			generation: self.subnet_generation.load(Ordering::Acquire),
+		}
+	}
+
+	fn enter_subnet(self: &Arc<Self>, subnet: u32) -> Option<SubnetSlot> {
+		let max = self.max_per_subnet.load(Ordering::Relaxed);
+		let mut subnets = self.subnets.lock().expect("subnet lock poisoned");
+		let running = subnets.entry(subnet).or_default();
+
+		if max > 0 && *running >= max {
+			return None;
+		}
+
+		*running += 1;
+		Some(SubnetSlot {
+			limits: self.clone(),
+			subnet,
+		})
+	}
+
+	// Waiting on a busy subnet first means no permit sits unused in the meantime
+	async fn acquire(&self, slot: SubnetSlot) -> Permit {
+		let permit = self
+			.permits
+			.clone()
+			.acquire_owned()
+			.await
+			.expect("semaphore closed");
+		self.in_flight.fetch_add(1, Ordering::Relaxed);
+
+		Permit {
+			permit: Some(permit),
+			slot,
+		}
+	}
+
+	fn subnet_freed(&self) {
+		self.subnet_generation.fetch_add(1, Ordering::Release);
+		self.subnet_freed.notify_waiters();
+	}
+
+	pub fn timeouts(&self) -> Timeouts {
+		Timeouts {
+			connect: Duration::from_millis(self.connect_timeout_ms.load(Ordering::Relaxed)),
+			read: Duration::from_millis(self.read_timeout_ms.load(Ordering::Relaxed)),
+		}
+	}
+
+	/// Waits for every task holding a permit to finish
+	pub async fn wait_for_tasks(&self) {
+		while self.in_flight.load(Ordering::Relaxed) > 0 {
+			tokio::time::sleep(Duration::from_millis(100)).await;
+		}
+	}
+
+	/// Changes the limits to the ones in the config, tasks that are already running are left alone
+	pub fn apply(&self, config: &ScannerConfig) {
+		let max_in_flight = config.max_in_flight.max(1);
+		let previous = self.max_in_flight.swap(max_in_flight, Ordering::Relaxed);
+
+		if max_in_flight > previous {
+			// Permits that haven't been taken away yet are kept instead of adding new ones
+			let increase = max_in_flight - previous;
+			let owed = self
+				.owed_permits
+				.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |owed| {
+					Some(owed.saturating_sub(increase))
+				})
+				.unwrap_or_default();
+
+			self.permits.add_permits(increase - owed.min(increase));
+		} else if max_in_flight < previous {
+			// Permits that are in use are taken away as soon as their tasks finish
+			let excess = previous - max_in_flight;
+			let forgotten = self.permits.forget_permits(excess);
+			self.owed_permits
+				.fetch_add(excess - forgotten, Ordering::Relaxed);
+		}
+
+		self.max_per_subnet
+			.store(config.max_per_subnet, Ordering::Relaxed);
+		self.connect_timeout_ms
+			.store(config.connect_timeout_ms, Ordering::Relaxed);
+		self.read_timeout_ms
+			.store(config.read_timeout_ms, Ordering::Relaxed);
+
+		// A higher subnet limit may let a deferred target through
+		self.subnet_freed();
+	}
+}
+
+impl<F: FnMut(SocketAddrV4, Permit)> Dispatcher<F> {
+	/// Starts a task for the target, or sets it aside if its subnet is busy. Only waits
+	/// when every permit is in use or too many targets have been set aside already
+	pub async fn push(&mut self, socket: SocketAddrV4) {
+		let subnet = socket.ip().to_bits() >> 8;
+
+		// Targets in a subnet with some already set aside have to wait their turn
+		let slot = match self.deferred.contains_key(&subnet) {
+			true => None,
+			false => self.limits.enter_subnet(subnet),
+		};
+
+		match slot {
+			Some(slot) => self.start(socket, slot).await,
+			None => {
+				self.deferred.entry(subnet).or_default().push_back(socket);
+				self.deferred_len += 1;
+			}
+		}
+
+		self.start_deferred().await;
+
+		while self.deferred_len >= MAX_DEFERRED {
+			self.wait_for_subnet().await;
+		}
+	}
+
+	/// Waits until every target that was set aside has been started
+	pub async fn drain(&mut self) {
+		while self.deferred_len > 0 {
+			self.wait_for_subnet().await;
+		}
+	}
+
+	/// Starts every target that was set aside, then waits for every task to finish
+	pub async fn finish(&mut self) {
+		self.drain().await;
+		self.limits.wait_for_tasks().await;
+	}
+
+	async fn start(&mut self, socket: SocketAddrV4, slot: SubnetSlot) {
+		let permit = self.limits.acquire(slot).await;
+		(self.spawn)(socket, permit);
+	}
+
+	// Starts deferred targets in subnets that have room again
+	async fn start_deferred(&mut self) {
+		let generation = self.limits.subnet_generation.load(Ordering::Acquire);
+		if generation == self.generation {
+			return;
+		}
+
+		self.generation = generation;
+		let subnets: Vec<u32> = self.deferred.keys().copied().collect();
+
+		for subnet in subnets {
+			while let Some(slot) = self.limits.enter_subnet(subnet) {
+				let Entry::Occupied(mut queue) = self.deferred.entry(subnet) else {
+					break;
+				};
+
+				let socket = queue
+					.get_mut()
+					.pop_front()
+					.expect("empty queues are removed");
+				if queue.get().is_empty() {
+					queue.remove();
+				}
+
+				self.deferred_len -= 1;
+				self.start(socket, slot).await;
+			}
+		}
+	}
+
+	async fn wait_for_subnet(&mut self) {
+		let limits = self.limits.clone();
+		let freed = limits.subnet_freed.notified();
+		if limits.subnet_generation.load(Ordering::Acquire) == self.generation {
+			freed.await;
+		}
+
+		self.start_deferred().await;
+	}
+}
+
+impl Drop for Permit {
+	fn drop(&mut self) {
+		let limits = &self.slot.limits;
+		limits.in_flight.fetch_sub(1, Ordering::Relaxed);
+
+		let owed = limits
+			.owed_permits
+			.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |owed| {
+				owed.checked_sub(1)
+			});
+
+		if let (Ok(_), Some(permit)) = (owed, self.permit.take()) {
+			permit.forget();
+		}
+	}
+}
+
+impl Drop for SubnetSlot {
+	fn drop(&mut self) {
+		let mut subnets = self.limits.subnets.lock().expect("subnet lock poisoned");
+		if let Entry::Occupied(mut running) = subnets.entry(self.subnet) {
+			*running.get_mut() -= 1;
+			if *running.get() == 0 {
+				running.remove();
+			}
+		}
+		drop(subnets);
+
+		self.limits.subnet_freed();
+	}
+}
+
+/// Reads the config file again every time the process receives a SIGHUP and applies
+/// the new limits from [scanner], everything else needs a restart to change
+pub async fn reload_on_hangup(limits: Arc<Limits>, config_file: String) {
+	let mut hangups = match signal(SignalKind::hangup()) {
+		Ok(hangups) => hangups,
+		Err(e) => {
+			warn!("Failed to listen for SIGHUP, limits can't be changed while running: {e}");
+			return;
+		}
+	};
+
+	while hangups.recv().await.is_some() {
+		match load_config(&config_file) {
+			Ok(config) => {
+				limits.apply(&config.scanner);
+				info!(
+					"Reloaded limits: {} in flight, {} per subnet, {}ms connect timeout, {}ms read timeout",
+					config.scanner.max_in_flight.max(1),
+					config.scanner.max_per_subnet,
+					config.scanner.connect_timeout_ms,
+					config.scanner.read_timeout_ms
+				);
+			}
+			Err(e) => error!("Failed to reload config file, keeping the current limits: {e}"),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::config::Config;
+	use std::net::Ipv4Addr;
+
+	fn limits(max_in_flight: usize, max_per_subnet: usize) -> Arc<Limits> {
+		let mut config = Config::default().scanner;
+		config.max_in_flight = max_in_flight;
+		config.max_per_subnet = max_per_subnet;
+		Arc::new(Limits::new(&config))
+	}
+
+	fn socket(address: [u8; 4]) -> SocketAddrV4 {
+		SocketAddrV4::new(Ipv4Addr::from(address), 25565)
+	}
+
+	#[tokio::test]
+	async fn busy_subnet_does_not_hold_up_others() {
+		let limits = limits(10, 1);
+		let mut started = Vec::new();
+		let mut dispatcher = limits.dispatcher(|socket, permit| started.push((socket, permit)));
+
+		for address in [[10, 0, 0, 1], [10, 0, 0, 2], [10, 0, 1, 1], [10, 0, 0, 3]] {
+			tokio::time::timeout(Duration::from_secs(1), dispatcher.push(socket(address)))
+				.await
+				.expect("push waited on a busy subnet");
+		}
+		drop(dispatcher);
+
+		let sockets: Vec<_> = started.iter().map(|(socket, _)| *socket).collect();
+		assert_eq!(sockets, vec![socket([10, 0, 0, 1]), socket([10, 0, 1, 1])]);
+	}
+
+	#[tokio::test]
+	async fn deferred_targets_start_when_subnet_frees() {
+		let limits = limits(10, 1);
+		let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+		let mut dispatcher = limits.dispatcher(|socket, permit| tx.send((socket, permit)).unwrap());
+
+		dispatcher.push(socket([10, 0, 0, 1])).await;
+		dispatcher.push(socket([10, 0, 0, 2])).await;
+		dispatcher.push(socket([10, 0, 0, 3])).await;
+
+		let (first, permit) = rx.recv().await.unwrap();
+		assert_eq!(first, socket([10, 0, 0, 1]));
+		assert!(rx.try_recv().is_err());
+
+		// Each target finishes as soon as it starts
+		let finish = async {
+			drop(permit);
+			let mut order = Vec::new();
+			while order.len() < 2 {
+				let (socket, permit) = rx.recv().await.unwrap();
+				order.push(socket);
+				drop(permit);
+			}
+			order
+		};
+
+		let (order, _) = tokio::join!(finish, dispatcher.finish());
+		assert_eq!(order, vec![socket([10, 0, 0, 2]), socket([10, 0, 0, 3])]);
+	}
+
+	#[tokio::test]
+	async fn raising_limit_again_restores_capacity() {
+		let limits = limits(4, 0);
+		let mut held = Vec::new();
+		let mut dispatcher = limits.dispatcher(|_, permit| held.push(permit));
+
+		for last in 1..=4 {
+			dispatcher.push(socket([10, 0, 0, last])).await;
+		}
+		drop(dispatcher);
+
+		// Every permit is in use, so they can only be taken away once their tasks finish
+		let mut config = Config::default().scanner;
+		config.max_per_subnet = 0;
+		config.max_in_flight = 1;
+		limits.apply(&config);
+		config.max_in_flight = 4;
+		limits.apply(&config);
+
+		held.clear();
+		assert_eq!(limits.permits.available_permits(), 4);
+
+		config.max_in_flight = 2;
+		limits.apply(&config);
+		assert_eq!(limits.permits.available_permits(), 2);
+	}
+
+	#[tokio::test]
+	async fn lowering_limit_takes_permits_back_from_finished_tasks() {
+		let limits = limits(4, 0);
+		let mut held = Vec::new();
+		let mut dispatcher = limits.dispatcher(|_, permit| held.push(permit));
+
+		for last in 1..=3 {
+			dispatcher.push(socket([10, 0, 0, last])).await;
+		}
+		drop(dispatcher);
+
+		let mut config = Config::default().scanner;
+		config.max_per_subnet = 0;
+		config.max_in_flight = 1;
+		limits.apply(&config);
+		assert_eq!(limits.permits.available_permits(), 0);
+
+		held.clear();
+		assert_eq!(limits.permits.available_permits(), 1);
+	}
+}
@@ -1,14 +1,26 @@
 #![feature(let_chains)]
 #![feature(string_from_utf8_lossy_owned)]
 
+mod bedrock;
 mod config;
 mod country_tracking;
 mod database;
+mod discovery;
+mod exclusions;
+mod favicon;
 mod geo_lookup;
+mod history;
+mod honeypot;
+mod limits;
+mod notifications;
+mod player_tracking;
 mod protocol;
+mod pruning;
+mod query;
 mod response;
 mod scanner;
 mod utils;
+mod varint;
 
 use crate::scanner::Scanner;
 use clap::Parser;
@@ -16,6 +28,7 @@ use config::load_config;
 use scanner::Mode;
 use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
 use sqlx::ConnectOptions;
+use std::net::{Ipv4Addr, SocketAddrV4};
 use std::time::Duration;
 use tracing::log::LevelFilter;
 use tracing::{error, info};
@@ -33,6 +46,18 @@ struct Args {
 	#[clap(default_value = "config.toml")]
 	#[clap(long, short = 'c')]
 	config_file: String,
+
+	#[clap(help = "Excludes an address or range from all scans, removes its servers and exits")]
+	#[clap(long)]
+	exclude: Option<String>,
+
+	#[clap(help = "Why the range is excluded, e.g. an abuse complaint")]
+	#[clap(long, requires = "exclude")]
+	reason: Option<String>,
+
+	#[clap(help = "Lets a server that opted out be scanned again, all ports if none is given, then exits")]
+	#[clap(long, value_name = "ADDRESS[:PORT]")]
+	opt_in: Option<String>,
 }
 
 #[tokio::main]
@@ -86,8 +111,53 @@ async fn main() {
 		std::process::exit(1);
 	}
 
+	if let Some(range) = &arguments.exclude
+		&& let Some(pool) = &pool
+	{
+		let Some(network) = discovery::parse_range(range) else {
+			error!("Invalid address or range: {range}");
+			std::process::exit(1);
+		};
+
+		match exclusions::exclude(pool, network, arguments.reason.as_deref()).await {
+			Ok(removed) => {
+				info!("Excluded {network} from all scans and removed {removed} servers");
+				std::process::exit(0);
+			}
+			Err(e) => {
+				error!("Failed to exclude {network}: {e}");
+				std::process::exit(1);
+			}
+		}
+	}
+
+	if let Some(server) = &arguments.opt_in
+		&& let Some(pool) = &pool
+	{
+		let (address, port) = match (server.parse::<SocketAddrV4>(), server.parse::<Ipv4Addr>()) {
+			(Ok(socket), _) => (*socket.ip(), Some(socket.port())),
+			(_, Ok(address)) => (address, None),
+			_ => {
+				error!("Invalid address: {server}");
+				std::process::exit(1);
+			}
+		};
+
+		match exclusions::opt_in(pool, address, port).await {
+			Ok(removed) => {
+				info!("Removed {removed} opt outs for {server}, it will be scanned again");
+				std::process::exit(0);
+			}
+			Err(e) => {
+				error!("Failed to remove opt out for {server}: {e}");
+				std::process::exit(1);
+			}
+		}
+	}
+
 	Scanner::new()
 		.config(config)
+		.config_file(arguments.config_file)
 		.mode(arguments.mode)
 		.pool(pool)
 		.build()
@@ -0,0 +1,554 @@
+use crate::config::{Notifications, ServerFilter, Webhook, WebhookFormat};
+use crate::history::Change;
+use crate::response::Server;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::net::SocketAddrV4;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::{self, error::TrySendError, Receiver, Sender};
+use tracing::{debug, warn};
+
+// Slow endpoints shouldn't hold up the queue for long
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+// Doubled after every failed attempt
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+// Longest message content Discord accepts, in characters
+const DISCORD_MAX_LENGTH: usize = 2000;
+
+/// Something that happened during a scan that webhooks can be notified about
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+	PlayerSeen {
+		server: String,
+		name: String,
+		// Players seen with query only have a name
+		uuid: Option<String>,
+	},
+	NewServer {
+		server: String,
+		// Name of the filter from the config that it matched
+		filter: String,
+		software: String,
+		version: String,
+		online_players: i32,
+		max_players: i32,
+		description: Option<String>,
+	},
+	ServerChanged {
+		server: String,
+		field: String,
+		old: String,
+		new: String,
+	},
+	ScanFinished {
+		mode: String,
+		duration_secs: u64,
+		total_servers: i64,
+	},
+}
+
+/// Used in the config to choose which events a webhook receives
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+	PlayerSeen,
+	NewServer,
+	ServerChanged,
+	ScanFinished,
+}
+
+impl Event {
+	pub fn kind(&self) -> EventKind {
+		match self {
+			Event::PlayerSeen { .. } => EventKind::PlayerSeen,
+			Event::NewServer { .. } => EventKind::NewServer,
+			Event::ServerChanged { .. } => EventKind::ServerChanged,
+			Event::ScanFinished { .. } => EventKind::ScanFinished,
+		}
+	}
+
+	/// Short human readable description, used for Discord messages
+	pub fn message(&self) -> String {
+		match self {
+			Event::PlayerSeen { server, name, uuid } => match uuid {
+				Some(uuid) => {
+					format!("👀 Tracked player **{name}** (`{uuid}`) was seen on `{server}`")
+				}
+				None => format!("👀 Tracked player **{name}** was seen on `{server}`"),
+			},
+			Event::NewServer {
+				server,
+				filter,
+				software,
+				version,
+				online_players,
+				max_players,
+				description,
+			} => {
+				let mut message = format!(
+					"🆕 New server `{server}` matching **{filter}**\n{software} {version}, {online_players}/{max_players} players"
+				);
+				if let Some(description) = description.as_ref().filter(|d| !d.is_empty()) {
+					message.push_str(&format!("\n> {}", description.replace('\n', "\n> ")));
+				}
+				message
+			}
+			Event::ServerChanged {
+				server,
+				field,
+				old,
+				new,
+			} => format!("🔄 `{server}` changed {field} from **{old}** to **{new}**"),
+			Event::ScanFinished {
+				mode,
+				duration_secs,
+				total_servers,
+			} => format!(
+				"✅ {mode} scan finished in {duration_secs} seconds, {total_servers} servers in the database"
+			),
+		}
+	}
+}
+
+/// Sends events to webhooks from background tasks, so a slow endpoint never holds up a scan.
+/// Each webhook has its own queue and task, so it doesn't hold up the others either
+#[derive(Debug, Clone, Default)]
+pub struct Notifier {
+	// Empty when notifications are disabled
+	queues: Arc<Vec<Queue>>,
+	filters: Arc<Vec<ServerFilter>>,
+}
+
+#[derive(Debug)]
+struct Queue {
+	// Webhook URLs contain a secret token, so webhooks are logged by index and host instead
+	name: String,
+	events: Vec<EventKind>,
+	sender: Sender<Event>,
+}
+
+impl Notifier {
+	/// Starts a delivery task for each webhook if notifications are enabled
+	pub fn new(config: &Notifications) -> Notifier {
+		if !config.enabled || config.webhooks.is_empty() {
+			return Notifier::default();
+		}
+
+		let client = match reqwest::Client::builder().timeout(REQUEST_TIMEOUT).build() {
+			Ok(client) => client,
+			Err(e) => {
+				warn!("Failed to create HTTP client for notifications: {e}");
+				return Notifier::default();
+			}
+		};
+
+		let queues = config
+			.webhooks
+			.iter()
+			.enumerate()
+			.map(|(index, webhook)| {
+				let name = webhook_name(index, &webhook.url);
+				let (sender, receiver) = mpsc::channel(config.queue_size.max(1));
+
+				tokio::spawn(deliver(
+					receiver,
+					client.clone(),
+					webhook.clone(),
+					name.clone(),
+					config.retries,
+				));
+
+				Queue {
+					name,
+					events: webhook.events.clone(),
+					sender,
+				}
+			})
+			.collect();
+
+		Notifier {
+			queues: Arc::new(queues),
+			filters: Arc::new(config.filters.clone()),
+		}
+	}
+
+	pub fn is_enabled(&self) -> bool {
+		!self.queues.is_empty()
+	}
+
+	/// Queues an event for every webhook that wants it, it's dropped for webhooks whose queue is full
+	pub fn send(&self, event: Event) {
+		let queues = self
+			.queues
+			.iter()
+			.filter(|q| q.events.is_empty() || q.events.contains(&event.kind()));
+
+		for queue in queues {
+			match queue.sender.try_send(event.clone()) {
+				Ok(_) => {}
+				Err(TrySendError::Full(event)) => warn!(
+					"Notification queue for {} is full, dropping {:?} event",
+					queue.name,
+					event.kind()
+				),
+				Err(TrySendError::Closed(_)) => {
+					debug!("Notification queue for {} has been closed", queue.name)
+				}
+			}
+		}
+	}
+
+	/// Events for a server that was just scanned, `changes` are the fields that
+	/// differ from what was stored for it, or None if it has never been seen
+	pub fn server_events(
+		&self,
+		socket: SocketAddrV4,
+		changes: Option<&[Change]>,
+		server: &Server,
+		description: Option<&str>,
+		country: &str,
+	) -> Vec<Event> {
+		if !self.is_enabled() {
+			return vec![];
+		}
+
+		let Some(changes) = changes else {
+			let software = format!("{:?}", server.get_type());
+
+			return self
+				.filters
+				.iter()
+				.filter(|f| f.matches(server, &software, description, country))
+				.map(|f| Event::NewServer {
+					server: socket.to_string(),
+					filter: f.name.clone(),
+					software: software.clone(),
+					version: server.version.name.clone(),
+					online_players: server.players.online,
+					max_players: server.players.max,
+					description: description.map(String::from),
+				})
+				.collect();
+		};
+
+		// Hashes of the MOTD and favicon wouldn't mean much in a message
+		changes
+			.iter()
+			.filter(|c| c.field == "software" || c.field == "version")
+			.filter_map(|c| match (&c.old, &c.new) {
+				(Some(old), Some(new)) => Some(Event::ServerChanged {
+					server: socket.to_string(),
+					field: String::from(c.field),
+					old: old.clone(),
+					new: new.clone(),
+				}),
+				_ => None,
+			})
+			.collect()
+	}
+}
+
+impl ServerFilter {
+	fn matches(
+		&self,
+		server: &Server,
+		software: &str,
+		description: Option<&str>,
+		country: &str,
+	) -> bool {
+		let contains =
+			|text: &str, pattern: &str| text.to_lowercase().contains(&pattern.to_lowercase());
+
+		self.software
+			.iter()
+			.all(|s| s.eq_ignore_ascii_case(software))
+			&& self
+				.version
+				.iter()
+				.all(|v| contains(&server.version.name, v))
+			&& self
+				.description
+				.iter()
+				.all(|d| contains(description.unwrap_or_default(), d))
+			&& self.country.iter().all(|c| c.eq_ignore_ascii_case(country))
+			&& self.min_players.iter().all(|&m| server.players.online >= m)
+	}
+}
+
+// e.g. "webhook #0 (discord.com)", without the path that holds the token
+fn webhook_name(index: usize, url: &str) -> String {
+	match reqwest::Url::parse(url)
+		.ok()
+		.and_then(|u| u.host_str().map(String::from))
+	{
+		Some(host) => format!("webhook #{index} ({host})"),
+		None => format!("webhook #{index}"),
+	}
+}
+
+/// Discord rejects messages over 2000 characters, and descriptions and player names come from
+/// scanned servers, so mentions like @everyone are never turned into pings
+fn discord_message(event: &Event) -> Value {
+	let message = event.message();
+	let content = match message.chars().count() > DISCORD_MAX_LENGTH {
+		true => message
+			.chars()
+			.take(DISCORD_MAX_LENGTH - 1)
+			.chain(['…'])
+			.collect(),
+		false => message,
+	};
+
+	json!({
+		"content": content,
+		"allowed_mentions": { "parse": [] },
+	})
+}
+
+async fn deliver(
+	mut receiver: Receiver<Event>,
+	client: reqwest::Client,
+	webhook: Webhook,
+	name: String,
+	retries: u32,
+) {
+	while let Some(event) = receiver.recv().await {
+		let body = match webhook.format {
+			WebhookFormat::Json => json!(event),
+			WebhookFormat::Discord => discord_message(&event),
+		};
+
+		let mut delay = RETRY_DELAY;
+		for attempt in 0..=retries {
+			match client.post(&webhook.url).json(&body).send().await {
+				Ok(response) if response.status().is_success() => break,
+				Ok(response) => debug!(
+					"{name} responded with {} (attempt {})",
+					response.status(),
+					attempt + 1
+				),
+				// The error would include the URL otherwise
+				Err(e) => debug!(
+					"{name} failed: {} (attempt {})",
+					e.without_url(),
+					attempt + 1
+				),
+			}
+
+			if attempt == retries {
+				warn!("Giving up on sending {:?} event to {name}", event.kind());
+				break;
+			}
+
+			tokio::time::sleep(delay).await;
+			delay *= 2;
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::collections::VecDeque;
+	use std::sync::Mutex;
+	use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+	use tokio::net::{TcpListener, TcpStream};
+	use tokio::sync::mpsc::UnboundedSender;
+
+	// Long enough for a request to arrive, short enough that a blocked queue fails the test
+	const RECEIVE_TIMEOUT: Duration = Duration::from_secs(5);
+
+	// Answers requests with the given statuses in order, then with 204. Bodies are sent down the channel
+	async fn stand_in(statuses: &[u16]) -> (String, mpsc::UnboundedReceiver<Value>) {
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let url = format!("http://{}/webhook", listener.local_addr().unwrap());
+		let statuses = Arc::new(Mutex::new(VecDeque::from(statuses.to_vec())));
+		let (sender, receiver) = mpsc::unbounded_channel();
+
+		tokio::spawn(async move {
+			while let Ok((stream, _)) = listener.accept().await {
+				tokio::spawn(answer(stream, statuses.clone(), sender.clone()));
+			}
+		});
+
+		(url, receiver)
+	}
+
+	async fn answer(
+		stream: TcpStream,
+		statuses: Arc<Mutex<VecDeque<u16>>>,
+		bodies: UnboundedSender<Value>,
+	) {
+		let mut stream = BufReader::new(stream);
+
+		// Connections are kept alive between retries
+		loop {
+			let mut content_length = 0;
+			loop {
+				let mut line = String::new();
+				if stream.read_line(&mut line).await.unwrap_or(0) == 0 {
+					return;
+				}
+
+				if line == "\r\n" {
+					break;
+				}
+
+				if let Some((name, value)) = line.split_once(':') {
+					if name.eq_ignore_ascii_case("content-length") {
+						content_length = value.trim().parse().unwrap();
+					}
+				}
+			}
+
+			let mut body = vec![0; content_length];
+			stream.read_exact(&mut body).await.unwrap();
+			bodies.send(serde_json::from_slice(&body).unwrap()).unwrap();
+
+			let status = statuses.lock().unwrap().pop_front().unwrap_or(204);
+			let response = format!("HTTP/1.1 {status} Stand-in\r\ncontent-length: 0\r\n\r\n");
+			stream
+				.get_mut()
+				.write_all(response.as_bytes())
+				.await
+				.unwrap();
+		}
+	}
+
+	fn notifier(webhooks: Vec<Webhook>, retries: u32) -> Notifier {
+		Notifier::new(&Notifications {
+			enabled: true,
+			retries,
+			webhooks,
+			..Default::default()
+		})
+	}
+
+	fn webhook(url: &str, format: WebhookFormat, events: Vec<EventKind>) -> Webhook {
+		Webhook {
+			url: url.to_string(),
+			format,
+			events,
+		}
+	}
+
+	fn player_seen(name: &str) -> Event {
+		Event::PlayerSeen {
+			server: String::from("127.0.0.1:25565"),
+			name: name.to_string(),
+			uuid: Some(String::from("069a79f4-44e9-4726-a5be-fca90e38aaf5")),
+		}
+	}
+
+	async fn receive(receiver: &mut mpsc::UnboundedReceiver<Value>) -> Value {
+		tokio::time::timeout(RECEIVE_TIMEOUT, receiver.recv())
+			.await
+			.expect("webhook wasn't called")
+			.unwrap()
+	}
+
+	#[tokio::test]
+	async fn sends_json_events() {
+		let (url, mut bodies) = stand_in(&[]).await;
+		let notifier = notifier(vec![webhook(&url, WebhookFormat::Json, vec![])], 0);
+
+		notifier.send(player_seen("Notch"));
+
+		let body = receive(&mut bodies).await;
+		assert_eq!(body["event"], "player_seen");
+		assert_eq!(body["name"], "Notch");
+		assert_eq!(body["server"], "127.0.0.1:25565");
+	}
+
+	#[tokio::test]
+	async fn discord_messages_never_mention() {
+		let (url, mut bodies) = stand_in(&[]).await;
+		let notifier = notifier(vec![webhook(&url, WebhookFormat::Discord, vec![])], 0);
+
+		notifier.send(Event::NewServer {
+			server: String::from("127.0.0.1:25565"),
+			filter: String::from("everything"),
+			software: String::from("Paper"),
+			version: String::from("1.21.4"),
+			online_players: 0,
+			max_players: 20,
+			description: Some(format!("@everyone @here <@&1234> {}", "a".repeat(3000))),
+		});
+
+		let body = receive(&mut bodies).await;
+		let content = body["content"].as_str().unwrap();
+
+		assert_eq!(body["allowed_mentions"], json!({ "parse": [] }));
+		assert!(content.contains("@everyone"));
+		assert_eq!(content.chars().count(), DISCORD_MAX_LENGTH);
+		assert!(content.ends_with('…'));
+	}
+
+	#[tokio::test]
+	async fn only_sends_chosen_events() {
+		let (url, mut bodies) = stand_in(&[]).await;
+		let notifier = notifier(
+			vec![webhook(
+				&url,
+				WebhookFormat::Json,
+				vec![EventKind::ScanFinished],
+			)],
+			0,
+		);
+
+		notifier.send(player_seen("Notch"));
+		notifier.send(Event::ScanFinished {
+			mode: String::from("rescan"),
+			duration_secs: 60,
+			total_servers: 10,
+		});
+
+		assert_eq!(receive(&mut bodies).await["event"], "scan_finished");
+	}
+
+	#[tokio::test]
+	async fn retries_failed_requests() {
+		let (url, mut bodies) = stand_in(&[500]).await;
+		let notifier = notifier(vec![webhook(&url, WebhookFormat::Json, vec![])], 1);
+
+		notifier.send(player_seen("Notch"));
+		notifier.send(player_seen("jeb_"));
+
+		// The first event is sent again before the second one
+		assert_eq!(receive(&mut bodies).await["name"], "Notch");
+		assert_eq!(receive(&mut bodies).await["name"], "Notch");
+		assert_eq!(receive(&mut bodies).await["name"], "jeb_");
+	}
+
+	#[tokio::test]
+	async fn slow_webhooks_dont_hold_up_others() {
+		// Accepts connections but never answers, so every request times out
+		let silent = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let silent_url = format!("http://{}/webhook", silent.local_addr().unwrap());
+		let (url, mut bodies) = stand_in(&[]).await;
+
+		let notifier = notifier(
+			vec![
+				webhook(&silent_url, WebhookFormat::Json, vec![]),
+				webhook(&url, WebhookFormat::Json, vec![]),
+			],
+			3,
+		);
+
+		notifier.send(player_seen("Notch"));
+		notifier.send(player_seen("jeb_"));
+
+		assert_eq!(receive(&mut bodies).await["name"], "Notch");
+		assert_eq!(receive(&mut bodies).await["name"], "jeb_");
+	}
+
+	#[test]
+	fn webhook_names_leave_out_the_token() {
+		let url = "https://discord.com/api/webhooks/1234/secret-token";
+
+		assert_eq!(webhook_name(0, url), "webhook #0 (discord.com)");
+		assert_eq!(webhook_name(1, "not a url"), "webhook #1");
+	}
+}
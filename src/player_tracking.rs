@@ -0,0 +1,117 @@
+use crate::config::PlayerTracking;
+use crate::response::Player;
+use sqlx::types::Uuid;
+use std::collections::HashSet;
+
+/// Players from the [player_tracking] config, each can be given as a name or a UUID
+#[derive(Debug, Default)]
+pub struct TrackedPlayers {
+	// Names are compared case insensitively, the same as Minecraft does
+	names: HashSet<String>,
+	uuids: HashSet<Uuid>,
+}
+
+impl From<&PlayerTracking> for TrackedPlayers {
+	fn from(config: &PlayerTracking) -> Self {
+		let mut tracked = TrackedPlayers::default();
+
+		if !config.enabled {
+			return tracked;
+		}
+
+		for player in config.players.iter().map(|p| p.trim()) {
+			match Uuid::parse_str(player) {
+				Ok(uuid) => {
+					tracked.uuids.insert(uuid);
+				}
+				Err(_) if !player.is_empty() => {
+					tracked.names.insert(player.to_lowercase());
+				}
+				Err(_) => (),
+			}
+		}
+
+		tracked
+	}
+}
+
+impl TrackedPlayers {
+	pub fn is_empty(&self) -> bool {
+		self.names.is_empty() && self.uuids.is_empty()
+	}
+
+	pub fn contains(&self, player: &Player) -> bool {
+		self.contains_name(&player.name)
+			|| Uuid::parse_str(&player.id).is_ok_and(|uuid| self.uuids.contains(&uuid))
+	}
+
+	/// Players from query only have a name, so players tracked by UUID can't match them
+	pub fn contains_name(&self, name: &str) -> bool {
+		self.names.contains(&name.to_lowercase())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const NOTCH_UUID: &str = "069a79f4-44e9-4726-a5be-fca90e38aaf5";
+
+	fn tracked(players: &[&str]) -> TrackedPlayers {
+		TrackedPlayers::from(&PlayerTracking {
+			enabled: true,
+			players: players.iter().map(|p| p.to_string()).collect(),
+		})
+	}
+
+	fn player(name: &str, id: &str) -> Player {
+		Player {
+			id: id.to_string(),
+			name: name.to_string(),
+		}
+	}
+
+	#[test]
+	fn matches_by_name_ignoring_case() {
+		let tracked = tracked(&["Notch", " jeb_ "]);
+
+		assert!(tracked.contains(&player("notch", "69b5a6cd-ba3b-3fb4-a43e-7a0e1e41fe73")));
+		assert!(tracked.contains(&player("JEB_", NOTCH_UUID)));
+		assert!(tracked.contains_name("NoTcH"));
+		assert!(!tracked.contains_name("Dinnerbone"));
+		assert!(!tracked.contains(&player("Notch2", NOTCH_UUID)));
+	}
+
+	#[test]
+	fn matches_by_uuid() {
+		// Both with and without dashes, in either case
+		let tracked = tracked(&[
+			"069A79F4-44E9-4726-A5BE-FCA90E38AAF5",
+			"853c80ef3c3749fdaa49938b674adae6",
+		]);
+
+		assert!(tracked.contains(&player("SomeoneElse", NOTCH_UUID)));
+		assert!(tracked.contains(&player("jeb_", "853c80ef-3c37-49fd-aa49-938b674adae6")));
+		assert!(!tracked.contains(&player(
+			"Dinnerbone",
+			"61699b2e-d327-4a01-9f1e-0ea8c3f06bc6"
+		)));
+
+		// A UUID is never compared against names
+		assert!(!tracked.contains_name(NOTCH_UUID));
+	}
+
+	#[test]
+	fn tracks_nobody_when_disabled_or_blank() {
+		let disabled = TrackedPlayers::from(&PlayerTracking {
+			enabled: false,
+			players: vec!["Notch".to_string()],
+		});
+		assert!(disabled.is_empty());
+		assert!(!disabled.contains_name("Notch"));
+
+		// The example config has a single empty entry
+		assert!(tracked(&["", "  "]).is_empty());
+		assert!(!tracked(&[""]).contains_name(""));
+	}
+}
@@ -1,9 +1,37 @@
+use crate::response::Server;
 use crate::utils::RunError;
+use crate::varint::{read_varint, VarInt};
+use serde_json::Value;
 use std::net::SocketAddrV4;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpStream;
 use tracing::debug;
 
+// Protocol version sent in handshakes unless configured otherwise (1.21.4)
+pub const DEFAULT_PROTOCOL_VERSION: i32 = 769;
+// How long to wait for a connection or a response unless configured otherwise
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(3);
+const HANDSHAKE_ID: u8 = 0;
+// Next state in the handshake: 1 for status, 2 for login
+const STATUS_STATE: i32 = 1;
+const LOGIN_STATE: i32 = 2;
+const STATUS_REQUEST: [u8; 2] = [
+	1, // Size
+	0, // ID
+];
+
+const STATUS_RESPONSE_ID: i32 = 0;
+// Max length of a string in the protocol is 32767 characters, each of which can be 3 bytes
+const MAX_STATUS_LENGTH: usize = 32767 * 3;
+// Packet ID, string length and the string
+const MAX_STATUS_PACKET_SIZE: usize = 1 + i32::MAX_BYTES + MAX_STATUS_LENGTH;
+
+const PING_ID: u8 = 1;
+const PONG_ID: i32 = 1;
+// Size of a ping or pong packet: packet ID and a long
+const PING_SIZE: u8 = 9;
+
 const SIMPLE_PAYLOAD: [u8; 9] = [
 	6, // Size: Amount of bytes in the message
 	0, // ID: Has to be 0
@@ -15,52 +43,242 @@ const SIMPLE_PAYLOAD: [u8; 9] = [
 	0, // ID
 ];
 
+const LOGIN_START_ID: u8 = 0;
+// Clientbound login packet IDs
+const LOGIN_DISCONNECT_ID: i32 = 0;
+const ENCRYPTION_REQUEST_ID: i32 = 1;
+const LOGIN_SUCCESS_ID: i32 = 2;
+const SET_COMPRESSION_ID: i32 = 3;
+const LOGIN_PLUGIN_REQUEST_ID: i32 = 4;
+// UUID sent in the login start packet by versions that require one
+const LOGIN_UUID: u128 = 0x5e7c1e2b_0f4d_4e8a_9a7b_3c2d1e0f4a5b;
+// Largest login packet we are willing to read
+const MAX_LOGIN_PACKET_SIZE: usize = 262144;
+// Protocol versions where the login start packet changed
+const PROTOCOL_1_19: i32 = 759;
+const PROTOCOL_1_19_1: i32 = 760;
+const PROTOCOL_1_19_3: i32 = 761;
+const PROTOCOL_1_20_2: i32 = 764;
+
+// 0xFE: Server list ping, 0x01: Payload, always 1. Understood by 1.4 and newer
+// 0xFA: Plugin message containing "MC|PingHost", only used by 1.6 but ignored by older versions
+const LEGACY_PAYLOAD: [u8; 3] = [0xFE, 0x01, 0xFA];
+// Protocol version sent inside the MC|PingHost plugin message (1.6.4)
+const LEGACY_PROTOCOL_VERSION: u8 = 78;
+// The largest possible kick packet, 1 byte packet ID, 2 byte length and 65535 UTF-16 characters
+const LEGACY_MAX_RESPONSE: u64 = 3 + (u16::MAX as u64 * 2);
+
 #[derive(Debug)]
 pub struct PingableServer {
 	pub socket: SocketAddrV4,
+	pub hostname: Option<String>,
+	pub protocol_version: i32,
+	pub measure_latency: bool,
+	pub connect_timeout: Duration,
+	pub read_timeout: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, sqlx::Type)]
+#[sqlx(type_name = "login_status", rename_all = "snake_case")]
+pub enum LoginStatus {
+	// Server sent an encryption request, players need a premium account
+	OnlineMode,
+	// Server let us in without authenticating
+	OfflineMode,
+	Whitelisted,
+	Banned,
+	VersionMismatch,
+	ModdedClientRequired,
+	// Disconnected for any other reason, see the disconnect reason
+	Disconnected,
+	Unknown,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoginProbe {
+	pub status: LoginStatus,
+	pub disconnect_reason: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct StatusResponse {
+	pub json: String,
+	// Round trip time of the ping and pong packets, if measured
+	pub latency: Option<Duration>,
 }
 
 impl PingableServer {
 	pub fn new(socket: SocketAddrV4) -> Self {
-		Self { socket }
+		Self {
+			socket,
+			hostname: None,
+			protocol_version: DEFAULT_PROTOCOL_VERSION,
+			measure_latency: false,
+			connect_timeout: DEFAULT_TIMEOUT,
+			read_timeout: DEFAULT_TIMEOUT,
+		}
+	}
+
+	/// Sets the hostname sent in the handshake and legacy ping, defaults to the servers IP address
+	pub fn hostname(mut self, hostname: Option<String>) -> Self {
+		self.hostname = hostname;
+		self
+	}
+
+	/// Sets the protocol version sent in the handshake
+	pub fn protocol_version(mut self, protocol_version: i32) -> Self {
+		self.protocol_version = protocol_version;
+		self
+	}
+
+	/// Sends a ping packet after the status response to measure the servers latency
+	pub fn measure_latency(mut self, measure_latency: bool) -> Self {
+		self.measure_latency = measure_latency;
+		self
+	}
+
+	/// Sets how long to wait for the connection to open
+	pub fn connect_timeout(mut self, connect_timeout: Duration) -> Self {
+		self.connect_timeout = connect_timeout;
+		self
 	}
 
-	pub async fn simple_ping(&self) -> Result<String, RunError> {
-		let mut stream = tokio::time::timeout(
-			crate::scanner::TIMEOUT_SECS,
-			TcpStream::connect(&self.socket),
+	/// Sets how long to wait for each response once connected
+	pub fn read_timeout(mut self, read_timeout: Duration) -> Self {
+		self.read_timeout = read_timeout;
+		self
+	}
+
+	/// Pings a server with a minimal handshake, this works for most servers
+	/// but proxies with forced hosts may not respond properly to it
+	pub async fn simple_ping(&self) -> Result<StatusResponse, RunError> {
+		let mut stream =
+			tokio::time::timeout(self.connect_timeout, TcpStream::connect(&self.socket)).await??;
+
+		stream.write_all(&SIMPLE_PAYLOAD).await?;
+		self.read_status(&mut stream).await
+	}
+
+	/// Pings a server with a complete handshake containing the hostname, port and
+	/// protocol version, just like a real client would
+	pub async fn proper_ping(&self) -> Result<StatusResponse, RunError> {
+		let mut stream =
+			tokio::time::timeout(self.connect_timeout, TcpStream::connect(&self.socket)).await??;
+
+		stream
+			.write_all(&self.build_handshake(self.protocol_version, STATUS_STATE))
+			.await?;
+		stream.write_all(&STATUS_REQUEST).await?;
+		self.read_status(&mut stream).await
+	}
+
+	/// Attempts to log in to the server with the given username and classifies the response.
+	/// The protocol version must match the servers version, otherwise it will just reject us
+	pub async fn login_probe(
+		&self,
+		protocol_version: i32,
+		username: &str,
+	) -> Result<LoginProbe, RunError> {
+		let mut stream =
+			tokio::time::timeout(self.connect_timeout, TcpStream::connect(&self.socket)).await??;
+
+		stream
+			.write_all(&self.build_handshake(protocol_version, LOGIN_STATE))
+			.await?;
+		stream
+			.write_all(&build_login_start(protocol_version, username))
+			.await?;
+
+		let (id, data) = tokio::time::timeout(
+			self.read_timeout,
+			read_packet(&mut stream, MAX_LOGIN_PACKET_SIZE),
 		)
 		.await??;
 
-		stream.write_all(&SIMPLE_PAYLOAD).await?;
-		let mut response = [0; 1024];
+		classify_login(id, &data)
+	}
+
+	/// The hostname sent to the server, proxies use it to pick which server to forward us to
+	fn host(&self) -> String {
+		match &self.hostname {
+			Some(hostname) => hostname.clone(),
+			None => self.socket.ip().to_string(),
+		}
+	}
+
+	/// Builds a handshake packet for the given next state, prefixed with its length
+	fn build_handshake(&self, protocol_version: i32, next_state: i32) -> Vec<u8> {
+		let hostname = self.host();
 
-		// The index is used to point to the position at the start of the string.
-		// It gets increased by the amount of bytes read to decode the packet ID, Packet length
-		// And string length
-		let mut index = 0;
+		let mut packet = vec![HANDSHAKE_ID];
+		protocol_version.encode(&mut packet);
+		(hostname.len() as i32).encode(&mut packet);
+		packet.extend_from_slice(hostname.as_bytes());
+		packet.extend_from_slice(&self.socket.port().to_be_bytes());
+		next_state.encode(&mut packet);
 
-		// Returns how many bytes were read from the stream into the buffer
-		let total_read_bytes = stream.read(&mut response).await?;
+		prefix_length(packet)
+	}
+
+	/// Reads a status response from the stream and measures the latency if requested
+	async fn read_status(&self, stream: &mut TcpStream) -> Result<StatusResponse, RunError> {
+		let json = self.read_json(stream).await?;
+
+		// A server that doesn't answer the ping still sent a valid status
+		let latency = match self.measure_latency {
+			true => match self.ping_pong(stream).await {
+				Ok(latency) => Some(latency),
+				Err(e) => {
+					debug!("[{}] Failed to measure latency: {e}", &self.socket.ip());
+					None
+				}
+			},
+			false => None,
+		};
+
+		Ok(StatusResponse { json, latency })
+	}
+
+	/// Sends a ping packet and times how long it takes for the server to respond with a pong
+	async fn ping_pong(&self, stream: &mut TcpStream) -> Result<Duration, RunError> {
+		// The payload can be anything, vanilla clients send the current time
+		let payload = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.unwrap_or_default()
+			.as_millis() as i64;
 
-		if total_read_bytes == 0 {
-			debug!("[{}] Total read bytes is 0", &self.socket.ip());
+		let mut packet = vec![PING_SIZE, PING_ID];
+		packet.extend_from_slice(&payload.to_be_bytes());
+
+		let start = Instant::now();
+		stream.write_all(&packet).await?;
+
+		// The pong packet should send back the same payload
+		let response = tokio::time::timeout(
+			self.read_timeout,
+			expect_packet(stream, PONG_ID, PING_SIZE as usize),
+		)
+		.await??;
+
+		if response != payload.to_be_bytes() {
 			return Err(RunError::MalformedResponse);
 		}
 
-		// Packet length
-		index += decode_varint(&response).1;
+		Ok(start.elapsed())
+	}
 
-		// Since Packet ID should always be 0 and will never take more than 1 byte to encode
-		// We can ignore it entirely and just advance the index by 1
-		index += 1;
+	/// Reads a status response packet and returns the JSON string inside of it
+	async fn read_json(&self, stream: &mut TcpStream) -> Result<String, RunError> {
+		let mut data = tokio::time::timeout(
+			self.read_timeout,
+			expect_packet(stream, STATUS_RESPONSE_ID, MAX_STATUS_PACKET_SIZE),
+		)
+		.await??;
 
-		// Decode the string length
-		let (string_length, string_length_bytes) = decode_varint(&response[index as usize..]);
-		index += string_length_bytes;
+		let (string_length, string_length_bytes) = i32::decode(&data)?;
 
 		// Error checking
-		if string_length == 0 || string_length > 32767 {
+		if string_length <= 0 || string_length as usize > MAX_STATUS_LENGTH {
 			debug!(
 				"[{}] String length: {string_length} was either 0 or too long",
 				&self.socket.ip()
@@ -68,55 +286,507 @@ impl PingableServer {
 			return Err(RunError::MalformedResponse);
 		}
 
-		// WARNING: Don't allocate vec size based on what the server says it needs from the varint.
-		// Allocate size based on what the server *actually* sends back, some servers can crash the
-		// program by attempting to allocate insane amounts of memory this way.
-		//
-		// Adds everything we have read so far minus the packet ID and packet length to a new vec
-		let mut output = Vec::from(&response[index as usize..total_read_bytes]);
-		let string_length = string_length + index as usize;
+		// The JSON should be the only thing in the packet
+		let json = data.split_off(string_length_bytes);
 
-		if total_read_bytes > string_length {
+		if json.len() != string_length as usize {
 			debug!(
-			"[{}] Total read bytes: {total_read_bytes} is larger than string length: {string_length}", &self.socket.ip()
-		);
+				"[{}] String length: {string_length} doesn't match the packet length: {}",
+				&self.socket.ip(),
+				json.len()
+			);
 			return Err(RunError::MalformedResponse);
 		}
 
-		// Read the rest of the servers JSON
-		stream
-			// Takes everything after the end of the data we already have in the buffer
-			// Up until the end of the strings length
-			.take((string_length - total_read_bytes) as u64)
-			.read_to_end(&mut output)
-			.await?;
+		Ok(String::from_utf8_lossy_owned(json))
+	}
+
+	/// Pings a server using the server list ping from before the netty rewrite (1.6 and below).
+	/// Servers respond with a kick packet containing the status as a UTF-16BE string
+	pub async fn legacy_ping(&self) -> Result<Server, RunError> {
+		let mut stream =
+			tokio::time::timeout(self.connect_timeout, TcpStream::connect(&self.socket)).await??;
+
+		stream.write_all(&self.build_legacy_payload()).await?;
+
+		// Legacy servers close the connection right after sending the kick packet
+		let mut response = Vec::new();
+		tokio::time::timeout(
+			self.read_timeout,
+			(&mut stream)
+				.take(LEGACY_MAX_RESPONSE)
+				.read_to_end(&mut response),
+		)
+		.await??;
 
-		Ok(String::from_utf8_lossy_owned(output))
+		// Packet ID of a kick packet is always 0xFF
+		if response.len() < 3 || response[0] != 0xFF {
+			debug!(
+				"[{}] Legacy response was not a kick packet",
+				&self.socket.ip()
+			);
+			return Err(RunError::MalformedResponse);
+		}
+
+		// Length of the string in characters, not bytes
+		let string_length = u16::from_be_bytes([response[1], response[2]]) as usize;
+		let string = &response[3..];
+
+		if string_length == 0 || string.len() < string_length * 2 {
+			debug!(
+				"[{}] Legacy string length: {string_length} was either 0 or too long",
+				&self.socket.ip()
+			);
+			return Err(RunError::MalformedResponse);
+		}
+
+		let characters = string[..string_length * 2]
+			.chunks_exact(2)
+			.map(|c| u16::from_be_bytes([c[0], c[1]]))
+			.collect::<Vec<u16>>();
+
+		Server::from_legacy(&String::from_utf16_lossy(&characters))
+			.ok_or(RunError::MalformedResponse)
 	}
 
-	// TODO
-	// pub async fn legacy_ping() {}
+	/// Builds the 1.6 style legacy ping, 1.4 and 1.5 servers stop reading after 0xFE 0x01
+	/// and beta 1.8 to 1.3 servers after 0xFE
+	fn build_legacy_payload(&self) -> Vec<u8> {
+		let channel = encode_utf16_be("MC|PingHost");
+		let hostname = encode_utf16_be(&self.host());
+
+		let mut payload = Vec::from(LEGACY_PAYLOAD);
 
-	// TODO
-	// pub async fn proper_ping() {}
+		// Channel name, prefixed by its length in characters
+		payload.extend_from_slice(&((channel.len() / 2) as u16).to_be_bytes());
+		payload.extend_from_slice(&channel);
+
+		// Length of the remaining data: protocol version, hostname length, hostname and port
+		payload.extend_from_slice(&((7 + hostname.len()) as u16).to_be_bytes());
+		payload.push(LEGACY_PROTOCOL_VERSION);
+		payload.extend_from_slice(&((hostname.len() / 2) as u16).to_be_bytes());
+		payload.extend_from_slice(&hostname);
+		payload.extend_from_slice(&(self.socket.port() as i32).to_be_bytes());
+
+		payload
+	}
 }
 
-// returns the decoded varint and how many bytes were read
+// Builds a login start packet, which has changed quite a bit over the versions
+fn build_login_start(protocol_version: i32, username: &str) -> Vec<u8> {
+	let mut packet = vec![LOGIN_START_ID];
+	(username.len() as i32).encode(&mut packet);
+	packet.extend_from_slice(username.as_bytes());
+
+	match protocol_version {
+		// 1.19: No signature data
+		PROTOCOL_1_19 => packet.push(0),
+		// 1.19.1 and 1.19.2: No signature data, no UUID
+		PROTOCOL_1_19_1 => packet.extend_from_slice(&[0, 0]),
+		// 1.19.3 to 1.20.1: Optional UUID
+		PROTOCOL_1_19_3..PROTOCOL_1_20_2 => {
+			packet.push(1);
+			packet.extend_from_slice(&LOGIN_UUID.to_be_bytes());
+		}
+		// 1.20.2 and newer: UUID is always sent
+		PROTOCOL_1_20_2.. => packet.extend_from_slice(&LOGIN_UUID.to_be_bytes()),
+		// Older versions only send the username
+		_ => {}
+	}
+
+	prefix_length(packet)
+}
+
+// Classifies the first packet the server sent back after the login start packet
+fn classify_login(id: i32, data: &[u8]) -> Result<LoginProbe, RunError> {
+	let status = match id {
+		ENCRYPTION_REQUEST_ID => LoginStatus::OnlineMode,
+		LOGIN_SUCCESS_ID | SET_COMPRESSION_ID => LoginStatus::OfflineMode,
+		LOGIN_DISCONNECT_ID => {
+			let reason = read_string(data).ok_or(RunError::MalformedResponse)?;
+
+			// Some plugins and proxies send plain text instead of a text component
+			let reason = match serde_json::from_str::<Value>(&reason) {
+				Ok(component) => {
+					let mut text = String::new();
+					collect_text(&component, &mut text);
+					text
+				}
+				Err(_) => reason,
+			};
+
+			return Ok(LoginProbe {
+				status: classify_disconnect(&reason),
+				disconnect_reason: Some(reason),
+			});
+		}
+		// Forge servers negotiate mods with login plugin requests on their own channel
+		LOGIN_PLUGIN_REQUEST_ID => {
+			// Message ID followed by the channel
+			let (_, message_id_bytes) = i32::decode(data)?;
+			match read_string(&data[message_id_bytes..]) {
+				Some(channel) if channel.starts_with("fml:") => LoginStatus::ModdedClientRequired,
+				_ => LoginStatus::Unknown,
+			}
+		}
+		_ => LoginStatus::Unknown,
+	};
+
+	Ok(LoginProbe {
+		status,
+		disconnect_reason: None,
+	})
+}
+
+// Guesses why we were disconnected from the disconnect message, vanilla servers
+// send translation keys that make this fairly reliable
+fn classify_disconnect(reason: &str) -> LoginStatus {
+	let reason = reason.to_lowercase();
+	let contains = |patterns: &[&str]| patterns.iter().any(|p| reason.contains(p));
+
+	if contains(&["whitelist", "white-list", "white list"]) {
+		LoginStatus::Whitelisted
+	} else if contains(&["banned"]) {
+		LoginStatus::Banned
+	} else if contains(&["forge", "fml", "modded", "mods", "fabric"]) {
+		LoginStatus::ModdedClientRequired
+	} else if contains(&["outdated", "incompatible", "version"]) {
+		LoginStatus::VersionMismatch
+	} else {
+		LoginStatus::Disconnected
+	}
+}
+
+// Collects the text and translation keys of a text component into a single string
+fn collect_text(value: &Value, output: &mut String) {
+	match value {
+		Value::String(s) => output.push_str(s),
+		Value::Array(array) => array.iter().for_each(|v| collect_text(v, output)),
+		Value::Object(object) => {
+			for key in ["text", "translate", "with", "extra"] {
+				if let Some(value) = object.get(key) {
+					collect_text(value, output);
+				}
+			}
+		}
+		_ => {}
+	}
+}
+
+// Reads a single packet from the stream and returns its ID and data.
+// Packets larger than max_size are rejected before anything else is read
+async fn read_packet<R: AsyncRead + Unpin>(
+	stream: &mut R,
+	max_size: usize,
+) -> Result<(i32, Vec<u8>), RunError> {
+	let length = read_varint(stream).await?;
+
+	if length <= 0 || length as usize > max_size {
+		return Err(RunError::MalformedResponse);
+	}
+
+	// WARNING: Don't allocate vec size based on what the server says it needs from the varint.
+	// Allocate size based on what the server *actually* sends back, some servers can crash the
+	// program by attempting to allocate insane amounts of memory this way.
+	let mut packet = Vec::new();
+	stream.take(length as u64).read_to_end(&mut packet).await?;
+
+	// Connection closed before the end of the packet
+	if packet.len() != length as usize {
+		return Err(RunError::MalformedResponse);
+	}
+
+	let (id, id_bytes) = i32::decode(&packet)?;
+	Ok((id, packet.split_off(id_bytes)))
+}
+
+// Reads a single packet and returns its data if it has the expected ID
+async fn expect_packet<R: AsyncRead + Unpin>(
+	stream: &mut R,
+	id: i32,
+	max_size: usize,
+) -> Result<Vec<u8>, RunError> {
+	match read_packet(stream, max_size).await? {
+		(packet_id, data) if packet_id == id => Ok(data),
+		_ => Err(RunError::MalformedResponse),
+	}
+}
+
+// Reads a varint prefixed string
+fn read_string(bytes: &[u8]) -> Option<String> {
+	let (length, length_bytes) = i32::decode(bytes).ok()?;
+	let string = bytes.get(length_bytes..length_bytes + usize::try_from(length).ok()?)?;
+
+	Some(String::from_utf8_lossy(string).into_owned())
+}
+
+// Prefixes a packet with its length
+fn prefix_length(packet: Vec<u8>) -> Vec<u8> {
+	let mut payload = Vec::with_capacity(packet.len() + i32::MAX_BYTES);
+	(packet.len() as i32).encode(&mut payload);
+	payload.extend_from_slice(&packet);
+
+	payload
+}
+
+// Encodes a string as UTF-16BE without a length prefix
 #[inline(always)]
-fn decode_varint(bytes: &[u8]) -> (usize, u8) {
-	let mut value: usize = 0;
-	let mut count: u8 = 0;
+fn encode_utf16_be(string: &str) -> Vec<u8> {
+	string.encode_utf16().flat_map(u16::to_be_bytes).collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::future::Future;
+	use std::net::Ipv4Addr;
+	use tokio::io::DuplexStream;
+	use tokio::net::TcpListener;
 
-	for b in bytes {
-		value |= ((b & 0x7F) as usize) << count;
+	const STATUS: &str =
+		r#"{"version":{"name":"1.21.4","protocol":769},"players":{"max":20,"online":0}}"#;
 
-		// right shift 7 times, if resulting value is 0 it means this is the end of the varint
-		if (b >> 7) != 1 {
-			break;
+	fn server() -> PingableServer {
+		PingableServer::new(SocketAddrV4::new(Ipv4Addr::new(192, 0, 2, 1), 25565))
+	}
+
+	// A packet with the given ID and data, prefixed with its length
+	fn packet(id: i32, data: &[u8]) -> Vec<u8> {
+		let mut packet = Vec::new();
+		id.encode(&mut packet);
+		packet.extend_from_slice(data);
+		prefix_length(packet)
+	}
+
+	fn string(string: &str) -> Vec<u8> {
+		let mut data = Vec::new();
+		(string.len() as i32).encode(&mut data);
+		data.extend_from_slice(string.as_bytes());
+		data
+	}
+
+	// A stream that hands over the bytes one at a time
+	fn trickle(bytes: Vec<u8>) -> DuplexStream {
+		let (mut server, client) = tokio::io::duplex(1);
+		tokio::spawn(async move { server.write_all(&bytes).await });
+		client
+	}
+
+	async fn write_one_at_a_time(stream: &mut TcpStream, bytes: &[u8]) {
+		for byte in bytes {
+			stream.write_all(&[*byte]).await.unwrap();
+			stream.flush().await.unwrap();
+			tokio::task::yield_now().await;
 		}
+	}
+
+	// Accepts a single connection and answers it with `respond`, one byte per segment
+	async fn stand_in<F, Fut>(respond: F) -> SocketAddrV4
+	where
+		F: FnOnce(TcpStream) -> Fut + Send + 'static,
+		Fut: Future<Output = ()> + Send,
+	{
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let socket = match listener.local_addr().unwrap() {
+			std::net::SocketAddr::V4(socket) => socket,
+			_ => unreachable!(),
+		};
 
-		count += 7;
+		tokio::spawn(async move {
+			let (stream, _) = listener.accept().await.unwrap();
+			stream.set_nodelay(true).unwrap();
+			respond(stream).await;
+		});
+
+		socket
 	}
 
-	(value, (count / 7) + 1)
+	#[tokio::test]
+	async fn reassembles_fragmented_packet() {
+		let bytes = packet(STATUS_RESPONSE_ID, &string(STATUS));
+		let data = expect_packet(
+			&mut trickle(bytes),
+			STATUS_RESPONSE_ID,
+			MAX_STATUS_PACKET_SIZE,
+		)
+		.await
+		.unwrap();
+
+		assert_eq!(data, string(STATUS));
+	}
+
+	#[tokio::test]
+	async fn rejects_oversized_packet() {
+		// Only the length is sent, the size cap rejects it before waiting for the rest
+		let mut bytes = Vec::new();
+		((MAX_STATUS_PACKET_SIZE + 1) as i32).encode(&mut bytes);
+
+		assert!(matches!(
+			read_packet(&mut trickle(bytes), MAX_STATUS_PACKET_SIZE).await,
+			Err(RunError::MalformedResponse)
+		));
+	}
+
+	#[tokio::test]
+	async fn rejects_empty_packet() {
+		assert!(matches!(
+			read_packet(&mut trickle(vec![0]), MAX_STATUS_PACKET_SIZE).await,
+			Err(RunError::MalformedResponse)
+		));
+	}
+
+	#[tokio::test]
+	async fn rejects_wrong_packet_id() {
+		let bytes = packet(PONG_ID, &string(STATUS));
+
+		assert!(matches!(
+			expect_packet(
+				&mut trickle(bytes),
+				STATUS_RESPONSE_ID,
+				MAX_STATUS_PACKET_SIZE
+			)
+			.await,
+			Err(RunError::MalformedResponse)
+		));
+	}
+
+	#[tokio::test]
+	async fn rejects_truncated_packet() {
+		let mut bytes = packet(STATUS_RESPONSE_ID, &string(STATUS));
+		bytes.truncate(bytes.len() - 1);
+
+		assert!(matches!(
+			read_packet(&mut trickle(bytes), MAX_STATUS_PACKET_SIZE).await,
+			Err(RunError::MalformedResponse)
+		));
+	}
+
+	#[tokio::test]
+	async fn reads_fragmented_status_and_pong() {
+		let socket = stand_in(|mut stream| async move {
+			let mut request = [0; SIMPLE_PAYLOAD.len()];
+			stream.read_exact(&mut request).await.unwrap();
+			write_one_at_a_time(&mut stream, &packet(STATUS_RESPONSE_ID, &string(STATUS))).await;
+
+			// A pong is the ping sent back as it is
+			let mut ping = [0; PING_SIZE as usize + 1];
+			stream.read_exact(&mut ping).await.unwrap();
+			write_one_at_a_time(&mut stream, &ping).await;
+		})
+		.await;
+
+		let response = PingableServer::new(socket)
+			.measure_latency(true)
+			.simple_ping()
+			.await
+			.unwrap();
+
+		assert_eq!(response.json, STATUS);
+		assert!(response.latency.is_some());
+	}
+
+	#[tokio::test]
+	async fn rejects_status_with_wrong_string_length() {
+		let socket = stand_in(|mut stream| async move {
+			let mut request = [0; SIMPLE_PAYLOAD.len()];
+			stream.read_exact(&mut request).await.unwrap();
+
+			// String length says there's one more byte than the packet holds
+			let mut data = Vec::new();
+			(STATUS.len() as i32 + 1).encode(&mut data);
+			data.extend_from_slice(STATUS.as_bytes());
+			write_one_at_a_time(&mut stream, &packet(STATUS_RESPONSE_ID, &data)).await;
+		})
+		.await;
+
+		assert!(matches!(
+			PingableServer::new(socket).simple_ping().await,
+			Err(RunError::MalformedResponse)
+		));
+	}
+
+	#[tokio::test]
+	async fn reads_fragmented_login_disconnect() {
+		let reason = r#"{"text":"You are not white-listed on this server!"}"#;
+		let socket = stand_in(move |mut stream| async move {
+			let mut request = [0; 1];
+			stream.read_exact(&mut request).await.unwrap();
+			write_one_at_a_time(&mut stream, &packet(LOGIN_DISCONNECT_ID, &string(reason))).await;
+
+			// Wait for the probe to hang up, so the rest of the request isn't answered with a reset
+			let _ = stream.read_to_end(&mut Vec::new()).await;
+		})
+		.await;
+
+		let probe = PingableServer::new(socket)
+			.login_probe(DEFAULT_PROTOCOL_VERSION, "ServerSeekerV2")
+			.await
+			.unwrap();
+
+		assert_eq!(probe.status, LoginStatus::Whitelisted);
+		assert_eq!(
+			probe.disconnect_reason.as_deref(),
+			Some("You are not white-listed on this server!")
+		);
+	}
+
+	fn disconnect(reason: &str) -> Vec<u8> {
+		let mut data = Vec::new();
+		(reason.len() as i32).encode(&mut data);
+		data.extend_from_slice(reason.as_bytes());
+		data
+	}
+
+	#[test]
+	fn classifies_json_disconnect() {
+		let reason = r#"{"translate":"multiplayer.disconnect.not_whitelisted"}"#;
+		let probe = classify_login(LOGIN_DISCONNECT_ID, &disconnect(reason)).unwrap();
+
+		assert_eq!(probe.status, LoginStatus::Whitelisted);
+		assert_eq!(
+			probe.disconnect_reason.as_deref(),
+			Some("multiplayer.disconnect.not_whitelisted")
+		);
+	}
+
+	#[test]
+	fn keeps_plain_text_disconnect() {
+		let reason = "You are banned from this server!";
+		let probe = classify_login(LOGIN_DISCONNECT_ID, &disconnect(reason)).unwrap();
+
+		assert_eq!(probe.status, LoginStatus::Banned);
+		assert_eq!(probe.disconnect_reason.as_deref(), Some(reason));
+	}
+
+	#[test]
+	fn rejects_disconnect_without_reason() {
+		assert!(matches!(
+			classify_login(LOGIN_DISCONNECT_ID, &[]),
+			Err(RunError::MalformedResponse)
+		));
+	}
+
+	#[test]
+	fn legacy_payload_sends_hostname() {
+		let payload = server()
+			.hostname(Some("mc.example.com".to_string()))
+			.build_legacy_payload();
+		let hostname = encode_utf16_be("mc.example.com");
+
+		assert!(payload.windows(hostname.len()).any(|w| w == hostname));
+		assert!(payload.ends_with(&25565_i32.to_be_bytes()));
+	}
+
+	#[test]
+	fn legacy_payload_defaults_to_address() {
+		let payload = server().build_legacy_payload();
+		let hostname = encode_utf16_be("192.0.2.1");
+
+		// Everything after the protocol version: hostname length, hostname and port
+		let mut expected = Vec::from(((hostname.len() / 2) as u16).to_be_bytes());
+		expected.extend_from_slice(&hostname);
+		expected.extend_from_slice(&25565_i32.to_be_bytes());
+
+		assert!(payload.ends_with(&expected));
+	}
 }
@@ -0,0 +1,90 @@
+use crate::config::{PruneAction, Pruning};
+use sqlx::PgPool;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+// How often servers that haven't been seen for a while are looked for
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const DAY: u64 = 60 * 60 * 24;
+// Everything else stored about a server, deleted along with it
+const SERVER_TABLES: &[&str] = &[
+	"players",
+	"query_players",
+	"player_sightings",
+	"plugins",
+	"mods",
+	"server_snapshots",
+	"server_changes",
+];
+// Unseen servers that nobody has left notes or a rating on in server_visits
+const DELETABLE: &str = "s.last_seen < $1 AND NOT EXISTS
+	(SELECT 1 FROM server_visits v WHERE v.address = s.address AND v.port = s.port)";
+
+/// Archives or deletes servers that haven't been seen for unseen_days every hour, forever
+pub async fn prune(pool: PgPool, config: Pruning) {
+	loop {
+		if let Err(e) = prune_once(&pool, &config).await {
+			warn!("Failed to prune unseen servers: {e}");
+		}
+
+		tokio::time::sleep(PRUNE_INTERVAL).await;
+	}
+}
+
+async fn prune_once(pool: &PgPool, config: &Pruning) -> anyhow::Result<()> {
+	let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+	let cutoff = now.saturating_sub(config.unseen_days * DAY) as i32;
+
+	match config.action {
+		PruneAction::Keep => {}
+		PruneAction::Archive => {
+			let result = sqlx::query(
+				"UPDATE servers SET archived_at = $2 WHERE last_seen < $1 AND archived_at IS NULL",
+			)
+			.bind(cutoff)
+			.bind(now as i32)
+			.execute(pool)
+			.await?;
+
+			info!("Archived {} unseen servers", result.rows_affected());
+		}
+		PruneAction::Delete => {
+			let mut transaction = pool.begin().await?;
+
+			// Visits would be deleted with the server, so servers that have them are archived instead
+			let archived = sqlx::query(
+				"UPDATE servers s SET archived_at = $2 WHERE last_seen < $1 AND archived_at IS NULL
+				AND EXISTS (SELECT 1 FROM server_visits v WHERE v.address = s.address AND v.port = s.port)",
+			)
+			.bind(cutoff)
+			.bind(now as i32)
+			.execute(&mut *transaction)
+			.await?;
+
+			for table in SERVER_TABLES {
+				sqlx::query(&format!(
+					"DELETE FROM {table} t USING servers s
+					WHERE t.address = s.address AND t.port = s.port AND {DELETABLE}"
+				))
+				.bind(cutoff)
+				.execute(&mut *transaction)
+				.await?;
+			}
+
+			let deleted = sqlx::query(&format!("DELETE FROM servers s WHERE {DELETABLE}"))
+				.bind(cutoff)
+				.execute(&mut *transaction)
+				.await?;
+
+			transaction.commit().await?;
+
+			info!(
+				"Deleted {} unseen servers, archived {} with visits",
+				deleted.rows_affected(),
+				archived.rows_affected()
+			);
+		}
+	}
+
+	Ok(())
+}
@@ -0,0 +1,384 @@
+use crate::protocol::DEFAULT_TIMEOUT;
+use crate::utils::RunError;
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tracing::debug;
+
+// Every query packet starts with these two bytes
+const QUERY_MAGIC: [u8; 2] = [0xFE, 0xFD];
+const HANDSHAKE_TYPE: u8 = 0x09;
+const STAT_TYPE: u8 = 0x00;
+// Only the lower 4 bits of each byte in the session ID are used by the server
+const SESSION_ID: i32 = 0x01010101;
+// Padding after the session ID in a full stat response, always "splitnum\0\x80\0"
+const STAT_PADDING: usize = 11;
+// Padding before the player list in a full stat response, always "\x01player_\0\0"
+const PLAYER_PADDING: usize = 10;
+const MAX_RESPONSE_SIZE: usize = 65535;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct QueryResponse {
+	pub description: Option<String>,
+	pub game_type: Option<String>,
+	pub version: Option<String>,
+	pub map: Option<String>,
+	pub online_players: Option<i32>,
+	pub max_players: Option<i32>,
+	// Server software as reported in the plugins field, e.g. "Paper on 1.21.4"
+	pub software: Option<String>,
+	pub plugins: Vec<Plugin>,
+	// Unlike the status response this contains every online player
+	pub players: Vec<String>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Plugin {
+	pub name: String,
+	pub version: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct QueryClient {
+	pub socket: SocketAddrV4,
+	pub timeout: Duration,
+}
+
+impl QueryClient {
+	pub fn new(socket: SocketAddrV4) -> Self {
+		Self {
+			socket,
+			timeout: DEFAULT_TIMEOUT,
+		}
+	}
+
+	/// Sets how long to wait for each response
+	pub fn timeout(mut self, timeout: Duration) -> Self {
+		self.timeout = timeout;
+		self
+	}
+
+	/// Requests the full stat of a server with enable-query=true
+	pub async fn query(&self) -> Result<QueryResponse, RunError> {
+		let udp = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0)).await?;
+		udp.connect(self.socket).await?;
+
+		// Handshake to receive a challenge token
+		let mut request = Vec::from(QUERY_MAGIC);
+		request.push(HANDSHAKE_TYPE);
+		request.extend_from_slice(&SESSION_ID.to_be_bytes());
+
+		let response = self.send(&udp, &request, HANDSHAKE_TYPE).await?;
+
+		// The challenge token is sent as a null terminated string of an integer
+		let challenge_token = read_string(&response)
+			.and_then(|(token, _)| token.parse::<i32>().ok())
+			.ok_or(RunError::MalformedResponse)?;
+
+		// Full stat request, the padding at the end is what separates it from a basic stat
+		let mut request = Vec::from(QUERY_MAGIC);
+		request.push(STAT_TYPE);
+		request.extend_from_slice(&SESSION_ID.to_be_bytes());
+		request.extend_from_slice(&challenge_token.to_be_bytes());
+		request.extend_from_slice(&[0, 0, 0, 0]);
+
+		let response = self.send(&udp, &request, STAT_TYPE).await?;
+
+		QueryResponse::parse(&response).ok_or(RunError::MalformedResponse)
+	}
+
+	/// Sends a request and returns the response without the type and session ID
+	async fn send(&self, udp: &UdpSocket, request: &[u8], kind: u8) -> Result<Vec<u8>, RunError> {
+		udp.send(request).await?;
+
+		let mut response = vec![0; MAX_RESPONSE_SIZE];
+		let total_read_bytes =
+			tokio::time::timeout(self.timeout, udp.recv(&mut response)).await??;
+		response.truncate(total_read_bytes);
+
+		if total_read_bytes < 5 || response[0] != kind || response[1..5] != SESSION_ID.to_be_bytes()
+		{
+			debug!("[{}] Invalid query response", &self.socket.ip());
+			return Err(RunError::MalformedResponse);
+		}
+
+		Ok(response.split_off(5))
+	}
+}
+
+impl QueryResponse {
+	/// Parses the key value section and the player section of a full stat response
+	pub fn parse(response: &[u8]) -> Option<QueryResponse> {
+		let mut index = STAT_PADDING;
+		let mut values = HashMap::new();
+
+		// Key value pairs, terminated by an empty key
+		loop {
+			let (key, read) = read_string(response.get(index..)?)?;
+			index += read;
+
+			if key.is_empty() {
+				break;
+			}
+
+			let (value, read) = read_string(response.get(index..)?)?;
+			index += read;
+
+			values.insert(key, value);
+		}
+
+		index += PLAYER_PADDING;
+
+		// Player names, terminated by an empty name
+		let mut players = Vec::new();
+		while let Some((name, read)) = response.get(index..).and_then(read_string) {
+			index += read;
+
+			if name.is_empty() {
+				break;
+			}
+
+			players.push(name);
+		}
+
+		let (software, plugins) = match values.remove("plugins") {
+			Some(plugins) => parse_plugins(&plugins),
+			None => (None, Vec::new()),
+		};
+
+		let mut value = |key: &str| values.remove(key).filter(|v| !v.is_empty());
+
+		Some(QueryResponse {
+			description: value("hostname"),
+			game_type: value("gametype"),
+			version: value("version"),
+			map: value("map"),
+			online_players: value("numplayers").and_then(|v| v.parse().ok()),
+			max_players: value("maxplayers").and_then(|v| v.parse().ok()),
+			software,
+			plugins,
+			players,
+		})
+	}
+}
+
+// Plugins are sent as "Paper on 1.21.4: WorldEdit 7.3.0; EssentialsX 2.20.1"
+// Vanilla servers send an empty string
+fn parse_plugins(plugins: &str) -> (Option<String>, Vec<Plugin>) {
+	let (software, plugins) = match plugins.split_once(':') {
+		Some((software, plugins)) => (software.trim(), plugins),
+		None => (plugins.trim(), ""),
+	};
+
+	let plugins = plugins
+		.split(';')
+		.map(str::trim)
+		.filter(|p| !p.is_empty())
+		.map(|plugin| match plugin.rsplit_once(' ') {
+			Some((name, version)) => Plugin {
+				name: name.to_string(),
+				version: Some(version.to_string()),
+			},
+			None => Plugin {
+				name: plugin.to_string(),
+				version: None,
+			},
+		})
+		.collect();
+
+	let software = match software.is_empty() {
+		true => None,
+		false => Some(software.to_string()),
+	};
+
+	(software, plugins)
+}
+
+// Returns a null terminated string and how many bytes were read, including the terminator
+#[inline(always)]
+fn read_string(bytes: &[u8]) -> Option<(String, usize)> {
+	let end = bytes.iter().position(|b| *b == 0)?;
+	Some((String::from_utf8_lossy(&bytes[..end]).into_owned(), end + 1))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const CHALLENGE_TOKEN: i32 = 9513307;
+
+	// Full stat of a server, without the type and session ID
+	fn full_stat(values: &[(&str, &str)], players: &[&str]) -> Vec<u8> {
+		let mut stat = b"splitnum\0\x80\0".to_vec();
+
+		for (key, value) in values {
+			stat.extend_from_slice(key.as_bytes());
+			stat.push(0);
+			stat.extend_from_slice(value.as_bytes());
+			stat.push(0);
+		}
+		stat.push(0);
+
+		stat.extend_from_slice(b"\x01player_\0\0");
+		for player in players {
+			stat.extend_from_slice(player.as_bytes());
+			stat.push(0);
+		}
+		stat.push(0);
+
+		stat
+	}
+
+	fn vanilla_stat() -> Vec<u8> {
+		full_stat(
+			&[
+				("hostname", "A Minecraft Server"),
+				("gametype", "SMP"),
+				("game_id", "MINECRAFT"),
+				("version", "1.21.4"),
+				("plugins", ""),
+				("map", "world"),
+				("numplayers", "2"),
+				("maxplayers", "20"),
+				("hostport", "25565"),
+				("hostip", "0.0.0.0"),
+			],
+			&["Notch", "jeb_"],
+		)
+	}
+
+	// Answers a handshake and a full stat request, with the given session ID in both answers
+	async fn stand_in(session_id: i32, stat: Vec<u8>) -> SocketAddrV4 {
+		let udp = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+		let socket = match udp.local_addr().unwrap() {
+			std::net::SocketAddr::V4(socket) => socket,
+			_ => unreachable!(),
+		};
+
+		tokio::spawn(async move {
+			let mut request = [0; 64];
+			let (_, client) = udp.recv_from(&mut request).await.unwrap();
+
+			let mut handshake = vec![HANDSHAKE_TYPE];
+			handshake.extend_from_slice(&session_id.to_be_bytes());
+			handshake.extend_from_slice(format!("{CHALLENGE_TOKEN}\0").as_bytes());
+			udp.send_to(&handshake, client).await.unwrap();
+
+			let (read, client) = udp.recv_from(&mut request).await.unwrap();
+			assert_eq!(read, 15);
+			assert_eq!(request[7..11], CHALLENGE_TOKEN.to_be_bytes());
+
+			let mut response = vec![STAT_TYPE];
+			response.extend_from_slice(&session_id.to_be_bytes());
+			response.extend_from_slice(&stat);
+			udp.send_to(&response, client).await.unwrap();
+		});
+
+		socket
+	}
+
+	#[test]
+	fn parses_vanilla_full_stat() {
+		let response = QueryResponse::parse(&vanilla_stat()).unwrap();
+
+		assert_eq!(
+			response,
+			QueryResponse {
+				description: Some("A Minecraft Server".to_string()),
+				game_type: Some("SMP".to_string()),
+				version: Some("1.21.4".to_string()),
+				map: Some("world".to_string()),
+				online_players: Some(2),
+				max_players: Some(20),
+				software: None,
+				plugins: vec![],
+				players: vec!["Notch".to_string(), "jeb_".to_string()],
+			}
+		);
+	}
+
+	#[test]
+	fn parses_bukkit_plugins() {
+		let stat = full_stat(
+			&[(
+				"plugins",
+				"CraftBukkit on Bukkit 1.21.4-R0.1: A 1.0; B 2.0; Standalone",
+			)],
+			&[],
+		);
+		let response = QueryResponse::parse(&stat).unwrap();
+
+		assert_eq!(
+			response.software.as_deref(),
+			Some("CraftBukkit on Bukkit 1.21.4-R0.1")
+		);
+		assert_eq!(
+			response.plugins,
+			vec![
+				Plugin {
+					name: "A".to_string(),
+					version: Some("1.0".to_string()),
+				},
+				Plugin {
+					name: "B".to_string(),
+					version: Some("2.0".to_string()),
+				},
+				Plugin {
+					name: "Standalone".to_string(),
+					version: None,
+				},
+			]
+		);
+		assert!(response.players.is_empty());
+	}
+
+	#[test]
+	fn parses_software_without_plugins() {
+		assert_eq!(
+			parse_plugins("Paper on 1.21.4"),
+			(Some("Paper on 1.21.4".to_string()), vec![])
+		);
+		assert_eq!(parse_plugins(""), (None, vec![]));
+	}
+
+	#[test]
+	fn rejects_truncated_full_stat() {
+		let stat = vanilla_stat();
+		// Cut off in the middle of the key value section
+		let end = stat.windows(4).position(|w| w == b"map\0").unwrap() + 2;
+
+		assert_eq!(QueryResponse::parse(&stat[..end]), None);
+		assert_eq!(QueryResponse::parse(&stat[..STAT_PADDING - 1]), None);
+	}
+
+	#[test]
+	fn keeps_players_read_before_truncation() {
+		let stat = vanilla_stat();
+		// Cut off in the middle of the second player
+		let end = stat.len() - 3;
+
+		let response = QueryResponse::parse(&stat[..end]).unwrap();
+		assert_eq!(response.players, vec!["Notch".to_string()]);
+	}
+
+	#[tokio::test]
+	async fn queries_stand_in() {
+		let socket = stand_in(SESSION_ID, vanilla_stat()).await;
+		let response = QueryClient::new(socket).query().await.unwrap();
+
+		assert_eq!(response.version.as_deref(), Some("1.21.4"));
+		assert_eq!(response.players.len(), 2);
+	}
+
+	#[tokio::test]
+	async fn rejects_other_session_id() {
+		let socket = stand_in(0x02020202, vanilla_stat()).await;
+		let result = QueryClient::new(socket)
+			.timeout(Duration::from_millis(500))
+			.query()
+			.await;
+
+		assert!(matches!(result, Err(RunError::MalformedResponse)));
+	}
+}
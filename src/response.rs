@@ -1,8 +1,14 @@
-use crate::utils::MinecraftColorCodes;
-use serde::{Deserialize, Serialize};
+use crate::protocol::{LoginProbe, LoginStatus};
+use crate::query::QueryResponse;
+use crate::varint::VarInt;
+use md5::{Digest, Md5};
+use serde::{Deserialize, Deserializer, Serialize};
 use serde_json::Value;
+use sqlx::types::Uuid;
+use text_component::Component;
+use tracing::debug;
 
-#[derive(Debug, sqlx::Type)]
+#[derive(Debug, Clone, Copy, PartialEq, sqlx::Type)]
 #[sqlx(type_name = "software")]
 pub enum ServerType {
 	Java,
@@ -19,8 +25,89 @@ pub enum ServerType {
 	Waterfall,
 	Bungeecord,
 	Thermos,
+	Fabric,
+	Quilt,
+	Sponge,
+	Mohist,
+	Magma,
+	Arclight,
+	Catserver,
+	Ketting,
+	Geyser,
+	Limbo,
+	Tcpshield,
 }
 
+// Something in a servers response that gives away what software it's running.
+// All text is matched case insensitively
+enum Fingerprint {
+	// Version name starts with
+	Version(&'static str),
+	// Version name contains
+	VersionContains(&'static str),
+	// Formatted description contains
+	Description(&'static str),
+	// Forge mod ID
+	Mod(&'static str),
+	// Plugin name starts with, only available from the query protocol
+	Plugin(&'static str),
+	// Neoforge sends an "isModded" field
+	Modded,
+	// Forge sends a "forgeData" object for modern servers and "modinfo" for legacy versions
+	Forge,
+}
+
+// Checked in order, the first fingerprint that matches decides the servers type
+#[rustfmt::skip]
+const FINGERPRINTS: &[(Fingerprint, ServerType)] = &[
+	// Hybrids send forge data too, so they have to be checked before forge
+	(Fingerprint::Version("mohist"), ServerType::Mohist),
+	(Fingerprint::Version("magma"), ServerType::Magma),
+	(Fingerprint::Version("arclight"), ServerType::Arclight),
+	(Fingerprint::Version("catserver"), ServerType::Catserver),
+	(Fingerprint::Version("ketting"), ServerType::Ketting),
+	(Fingerprint::Version("thermos"), ServerType::Thermos),
+	// Proxies and limbo servers, these usually keep their default description
+	// when there is no backend server to pass the ping through to
+	(Fingerprint::Version("velocity"), ServerType::Velocity),
+	(Fingerprint::Description("a velocity server"), ServerType::Velocity),
+	(Fingerprint::Version("waterfall"), ServerType::Waterfall),
+	(Fingerprint::Version("bungeecord"), ServerType::Bungeecord),
+	(Fingerprint::Description("another bungee server"), ServerType::Bungeecord),
+	(Fingerprint::VersionContains("tcpshield"), ServerType::Tcpshield),
+	(Fingerprint::Description("tcpshield"), ServerType::Tcpshield),
+	(Fingerprint::Version("nanolimbo"), ServerType::Limbo),
+	(Fingerprint::Description("nanolimbo"), ServerType::Limbo),
+	// Java servers that Bedrock players can join as well
+	(Fingerprint::Plugin("geyser"), ServerType::Geyser),
+	(Fingerprint::Plugin("floodgate"), ServerType::Geyser),
+	// Mod loaders
+	(Fingerprint::Modded, ServerType::Neoforge),
+	(Fingerprint::Mod("neoforge"), ServerType::Neoforge),
+	(Fingerprint::Mod("quilt_loader"), ServerType::Quilt),
+	(Fingerprint::Mod("fabricloader"), ServerType::Fabric),
+	(Fingerprint::Mod("fabric-api"), ServerType::Fabric),
+	(Fingerprint::Mod("sponge"), ServerType::Sponge),
+	(Fingerprint::Version("sponge"), ServerType::Sponge),
+	(Fingerprint::Forge, ServerType::Lexforge),
+	// Plugin servers
+	(Fingerprint::Version("paper"), ServerType::Paper),
+	(Fingerprint::Version("spigot"), ServerType::Spigot),
+	(Fingerprint::Version("craftbukkit"), ServerType::Bukkit),
+	(Fingerprint::Version("bukkit"), ServerType::Bukkit),
+	(Fingerprint::Version("purpur"), ServerType::Purpur),
+	(Fingerprint::Version("folia"), ServerType::Folia),
+	(Fingerprint::Version("pufferfish"), ServerType::Pufferfish),
+	(Fingerprint::Version("leaves"), ServerType::Leaves),
+];
+
+// Version name given to servers that respond to the legacy ping without a version
+const LEGACY_VERSION_NAME: &str = "Beta 1.8 - 1.3";
+// Name vanilla gives players in the sample that have disabled "Allow Server Listings"
+const ANONYMOUS_PLAYER_NAME: &str = "Anonymous Player";
+// Servers with this in their description are removed and never scanned again
+pub const OPT_OUT_MARKER: &str = "§b§d§f§d§b";
+
 #[allow(dead_code)]
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Server {
@@ -40,6 +127,15 @@ pub struct Server {
 	// "modinfo" is for legacy versions of forge
 	#[serde(rename = "forgeData", alias = "modinfo")]
 	pub forge_data: Option<ForgeData>,
+	// Not part of the response, measured with a ping packet after the status response
+	#[serde(skip)]
+	pub latency_ms: Option<i32>,
+	// Not part of the response, requested separately over the query protocol
+	#[serde(skip)]
+	pub query: Option<QueryResponse>,
+	// Not part of the response, result of attempting to log in to the server
+	#[serde(skip)]
+	pub login: Option<LoginProbe>,
 }
 
 #[allow(dead_code)]
@@ -65,12 +161,35 @@ pub struct Player {
 }
 
 #[allow(dead_code)]
-#[derive(Deserialize, Serialize, PartialEq, Clone, Debug)]
+#[derive(Serialize, PartialEq, Clone, Debug)]
 pub struct ForgeData {
+	pub mods: Vec<Mod>,
+	pub channels: Vec<Channel>,
+	// Forge leaves out mods and channels when the response would be too large
+	pub truncated: bool,
+}
+
+#[derive(Deserialize)]
+struct RawForgeData {
 	// "mods", is for modern versions of forge
 	// "modList" is legacy forge versions
-	#[serde(rename = "mods", alias = "modList")]
-	pub mods: Vec<Mod>,
+	#[serde(default, rename = "mods", alias = "modList")]
+	mods: Vec<Mod>,
+	#[serde(default)]
+	channels: Vec<Channel>,
+	#[serde(default)]
+	truncated: bool,
+	// Forge 1.18.2 and newer send mods and channels encoded into this string instead
+	d: Option<String>,
+}
+
+#[allow(dead_code)]
+#[derive(Deserialize, Serialize, PartialEq, Clone, Debug)]
+pub struct Channel {
+	#[serde(rename = "res")]
+	pub name: String,
+	pub version: String,
+	pub required: bool,
 }
 
 #[allow(dead_code)]
@@ -82,111 +201,834 @@ pub struct Mod {
 	pub version: String,
 }
 
+// Mod version Forge sends for mods that don't need to be installed on the client
+const IGNORE_SERVER_ONLY: &str = "OHNOES\u{1F631}\u{1F631}\u{1F631}\u{1F631}";
+
+/// What an entry in the player sample actually is
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlayerKind {
+	// v4 UUID given out by Mojang
+	OnlineMode,
+	// v3 UUID made from the players name by an offline mode server
+	OfflineMode,
+	// Bedrock player joining through Floodgate, the first half of the UUID is zeroed
+	Bedrock,
+	// Placeholder for a player that has disabled "Allow Server Listings"
+	Anonymous,
+	// Anything else, usually lines of text added to the sample by a plugin
+	Text,
+}
+
+/// Whether a server checks players with Mojang, as seen from its players UUIDs
+#[derive(Debug, Clone, Copy, PartialEq, sqlx::Type)]
+#[sqlx(type_name = "auth_mode", rename_all = "snake_case")]
+pub enum AuthMode {
+	Online,
+	Offline,
+	// Both kinds of players, e.g. a proxy with some offline mode backends or a login plugin
+	Mixed,
+}
+
+impl Player {
+	pub fn kind(&self) -> PlayerKind {
+		let Ok(uuid) = Uuid::parse_str(&self.id) else {
+			return PlayerKind::Text;
+		};
+
+		let (high, low) = uuid.as_u64_pair();
+
+		match uuid.get_version_num() {
+			_ if self.name.contains('§') => PlayerKind::Text,
+			_ if uuid.is_nil() && self.name == ANONYMOUS_PLAYER_NAME => PlayerKind::Anonymous,
+			4 => PlayerKind::OnlineMode,
+			3 => PlayerKind::OfflineMode,
+			_ if high == 0 && low != 0 => PlayerKind::Bedrock,
+			_ => PlayerKind::Text,
+		}
+	}
+
+	/// Offline mode servers give players a v3 UUID of the MD5 hash of "OfflinePlayer:<name>"
+	pub fn offline_uuid(name: &str) -> Uuid {
+		let mut hash: [u8; 16] = Md5::digest(format!("OfflinePlayer:{name}")).into();
+		hash[6] = (hash[6] & 0x0F) | 0x30;
+		hash[8] = (hash[8] & 0x3F) | 0x80;
+		Uuid::from_bytes(hash)
+	}
+
+	/// Is this an actual player that was online at the time?
+	pub fn is_real(&self) -> bool {
+		matches!(
+			self.kind(),
+			PlayerKind::OnlineMode | PlayerKind::OfflineMode | PlayerKind::Bedrock
+		)
+	}
+}
+
+impl Players {
+	/// Lines of text added to the sample, servers use these as a second description
+	pub fn sample_text(&self) -> Option<String> {
+		let lines: Vec<&str> = self
+			.sample
+			.iter()
+			.flatten()
+			.filter(|p| p.kind() == PlayerKind::Text)
+			.map(|p| p.name.as_str())
+			.collect();
+
+		match lines.is_empty() {
+			true => None,
+			false => Some(lines.join("\n")),
+		}
+	}
+
+	/// Infers the servers auth mode from the players in the sample. A v3 UUID only counts
+	/// if it was made from the players name, and Bedrock players are the same either way
+	pub fn auth_mode(&self) -> Option<AuthMode> {
+		let (mut online, mut offline) = (false, false);
+
+		for player in self.sample.iter().flatten() {
+			match player.kind() {
+				PlayerKind::OnlineMode => online = true,
+				PlayerKind::OfflineMode => {
+					offline |= Uuid::parse_str(&player.id)
+						.is_ok_and(|uuid| uuid == Player::offline_uuid(&player.name))
+				}
+				_ => (),
+			}
+		}
+
+		match (online, offline) {
+			(true, true) => Some(AuthMode::Mixed),
+			(true, false) => Some(AuthMode::Online),
+			(false, true) => Some(AuthMode::Offline),
+			(false, false) => None,
+		}
+	}
+
+	/// Whether the server hides who is online, either with "hide-online-players"
+	/// or by replacing the sample. Unknown when nobody is online
+	pub fn hides_players(&self) -> Option<bool> {
+		if self.online <= 0 {
+			return None;
+		}
+
+		Some(!self.sample.iter().flatten().any(Player::is_real))
+	}
+}
+
+// Deserialized through RawForgeData, so the "d" field can be decoded into mods and channels
+impl<'de> Deserialize<'de> for ForgeData {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		RawForgeData::deserialize(deserializer).map(ForgeData::from)
+	}
+}
+
+impl From<RawForgeData> for ForgeData {
+	fn from(raw: RawForgeData) -> Self {
+		let mut forge_data = ForgeData {
+			mods: raw.mods,
+			channels: raw.channels,
+			truncated: raw.truncated,
+		};
+
+		if let Some(d) = raw.d {
+			let decoded = decode_optimized(&d).and_then(|bytes| forge_data.read_optimized(&bytes));
+
+			if decoded.is_none() {
+				debug!("Failed to decode forge data");
+			}
+		}
+
+		forge_data
+	}
+}
+
+impl ForgeData {
+	/// Reads mods and channels from the buffer decoded from the "d" field.
+	/// Returns None if the buffer ends early
+	fn read_optimized(&mut self, bytes: &[u8]) -> Option<()> {
+		let mut reader = Reader { bytes, index: 0 };
+
+		self.truncated = reader.read_bool()?;
+
+		let mods = u16::from_be_bytes([reader.read_u8()?, reader.read_u8()?]);
+		for _ in 0..mods {
+			// Lowest bit is set for mods that aren't required on the client,
+			// the rest is the amount of channels belonging to this mod
+			let flags = reader.read_varint()?;
+			let id = reader.read_string()?;
+			let version = match flags & 1 {
+				1 => IGNORE_SERVER_ONLY.to_string(),
+				_ => reader.read_string()?,
+			};
+
+			for _ in 0..(flags >> 1) {
+				let name = reader.read_string()?;
+				self.channels.push(Channel {
+					name: format!("{id}:{name}"),
+					version: reader.read_string()?,
+					required: reader.read_bool()?,
+				});
+			}
+
+			self.mods.push(Mod { id, version });
+		}
+
+		// Channels that don't belong to any mod
+		for _ in 0..reader.read_varint()? {
+			self.channels.push(Channel {
+				name: reader.read_string()?,
+				version: reader.read_string()?,
+				required: reader.read_bool()?,
+			});
+		}
+
+		Some(())
+	}
+}
+
+// Forge packs 15 bits into each UTF-16 character of the string, the first two characters
+// are the length of the decoded buffer
+fn decode_optimized(string: &str) -> Option<Vec<u8>> {
+	let mut characters = string.encode_utf16();
+	let size = characters.next()? as usize | ((characters.next()? as usize) << 15);
+
+	let mut output = Vec::with_capacity(size.min(string.len() * 2));
+	let mut buffer: u32 = 0;
+	let mut bits_in_buffer = 0;
+
+	for c in characters {
+		while bits_in_buffer >= 8 {
+			output.push(buffer as u8);
+			buffer >>= 8;
+			bits_in_buffer -= 8;
+		}
+
+		buffer |= ((c & 0x7FFF) as u32) << bits_in_buffer;
+		bits_in_buffer += 15;
+	}
+
+	// Leftover bits
+	while output.len() < size && bits_in_buffer > 0 {
+		output.push(buffer as u8);
+		buffer >>= 8;
+		bits_in_buffer -= 8;
+	}
+
+	match output.len() == size {
+		true => Some(output),
+		false => None,
+	}
+}
+
+// Reads values from the buffer decoded from the forge "d" field
+struct Reader<'a> {
+	bytes: &'a [u8],
+	index: usize,
+}
+
+impl Reader<'_> {
+	fn read_u8(&mut self) -> Option<u8> {
+		let byte = *self.bytes.get(self.index)?;
+		self.index += 1;
+		Some(byte)
+	}
+
+	fn read_bool(&mut self) -> Option<bool> {
+		self.read_u8().map(|b| b != 0)
+	}
+
+	fn read_varint(&mut self) -> Option<i32> {
+		let (value, read) = i32::decode(self.bytes.get(self.index..)?).ok()?;
+		self.index += read;
+		Some(value)
+	}
+
+	fn read_string(&mut self) -> Option<String> {
+		let length = usize::try_from(self.read_varint()?).ok()?;
+		let string = self.bytes.get(self.index..self.index + length)?;
+		self.index += length;
+		Some(String::from_utf8_lossy(string).into_owned())
+	}
+}
+
 impl Server {
+	/// Builds a server from the kick message of a legacy server list ping.
+	///
+	/// 1.4 to 1.6 respond with `§1\0protocol\0version\0motd\0online\0max`,
+	/// beta 1.8 to 1.3 respond with `motd§online§max`
+	pub fn from_legacy(response: &str) -> Option<Server> {
+		let (version, description, online, max) = match response.strip_prefix("§1\0") {
+			Some(response) => {
+				let mut fields = response.split('\0');
+
+				let protocol = fields.next()?.parse::<i32>().ok()?;
+				let name = fields.next()?.to_string();
+				let description = fields.next()?;
+
+				(
+					Version { name, protocol },
+					description,
+					fields.next()?,
+					fields.next()?,
+				)
+			}
+			None => {
+				// The description can contain section signs, the player counts can't
+				let mut fields = response.rsplitn(3, '§');
+
+				let max = fields.next()?;
+				let online = fields.next()?;
+				let description = fields.next()?;
+
+				// Versions this old don't report their version or protocol
+				let version = Version {
+					name: LEGACY_VERSION_NAME.to_string(),
+					protocol: -1,
+				};
+
+				(version, description, online, max)
+			}
+		};
+
+		Some(Server {
+			version,
+			favicon: None,
+			players: Players {
+				max: max.parse().ok()?,
+				online: online.parse().ok()?,
+				sample: None,
+			},
+			description_raw: Some(Value::String(description.to_string())),
+			description_formatted: None,
+			prevents_reports: None,
+			enforces_secure_chat: None,
+			modded: None,
+			forge_data: None,
+			latency_ms: None,
+			query: None,
+			login: None,
+		})
+	}
+
 	pub fn get_type(&self) -> ServerType {
-		// Neoforge sends an "isModded" field
-		if self.modded.is_some() {
-			return ServerType::Neoforge;
-		}
+		let version = self.version.name.to_lowercase();
+		let description = self
+			.description_raw
+			.as_ref()
+			.map(|v| self.build_formatted_description(v).to_lowercase())
+			.unwrap_or_default();
+
+		FINGERPRINTS
+			.iter()
+			.find(|(fingerprint, _)| self.matches(fingerprint, &version, &description))
+			.map(|(_, server_type)| *server_type)
+			.unwrap_or(ServerType::Java)
+	}
+
+	// Version and description are passed in already lowercase
+	fn matches(&self, fingerprint: &Fingerprint, version: &str, description: &str) -> bool {
+		let mods = self.forge_data.iter().flat_map(|f| &f.mods);
+		let plugins = self.query.iter().flat_map(|q| &q.plugins);
 
-		// Forge sends a "forgeData" object for modern servers
-		// and "modinfo" for legacy versions
-		if self.forge_data.is_some() {
-			return ServerType::Lexforge;
+		match fingerprint {
+			Fingerprint::Version(name) => version.starts_with(name),
+			Fingerprint::VersionContains(name) => version.contains(name),
+			Fingerprint::Description(text) => description.contains(text),
+			Fingerprint::Mod(id) => mods.into_iter().any(|m| m.id.eq_ignore_ascii_case(id)),
+			Fingerprint::Plugin(name) => plugins
+				.into_iter()
+				.any(|p| p.name.to_lowercase().starts_with(name)),
+			Fingerprint::Modded => self.modded.is_some(),
+			Fingerprint::Forge => self.forge_data.is_some(),
 		}
+	}
 
-		match self.version.name.split(' ').nth(0) {
-			Some("Paper") => ServerType::Paper,
-			Some("Spigot") => ServerType::Spigot,
-			Some("Bukkit") => ServerType::Bukkit,
-			Some("Purpur") => ServerType::Purpur,
-			Some("Folia") => ServerType::Folia,
-			Some("Pufferfish") => ServerType::Pufferfish,
-			Some("Velocity") => ServerType::Velocity,
-			Some("Leaves") => ServerType::Leaves,
-			Some("Waterfall") => ServerType::Waterfall,
-			Some("Bungeecord") => ServerType::Bungeecord,
-			_ => ServerType::Java,
+	/// Auth mode from the login probe if one was done, otherwise inferred from the player sample
+	pub fn auth_mode(&self) -> Option<AuthMode> {
+		match self.login.as_ref().map(|l| l.status) {
+			Some(LoginStatus::OnlineMode) => Some(AuthMode::Online),
+			Some(LoginStatus::OfflineMode) => Some(AuthMode::Offline),
+			_ => self.players.auth_mode(),
 		}
 	}
 
-	// Has the user opted out of scanning?
+	// Has the user opted out of scanning? The raw JSON is checked too, in case
+	// the marker is split up in a way that formatting doesn't keep
 	pub fn check_opt_out(&self) -> bool {
-		match &self.description_formatted {
-			Some(description) => String::from(description).contains("§b§d§f§d§b"),
-			None => false,
-		}
+		let Some(raw) = &self.description_raw else {
+			return false;
+		};
+
+		self.build_formatted_description(raw)
+			.contains(OPT_OUT_MARKER)
+			|| raw.to_string().contains(OPT_OUT_MARKER)
 	}
 
-	#[rustfmt::skip]
+	// Renders the description with § codes, see Component for what is supported
 	pub fn build_formatted_description(&self, value: &Value) -> String {
-		let mut output = String::new();
+		Component::from(value).to_legacy()
+	}
+}
 
-		match value {
-			Value::String(s) => output.push_str(s),
-			Value::Array(array) => {
-				for value in array {
-					output.push_str(&self.build_formatted_description(value));
-				}
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::query::Plugin;
+
+	// Notch's UUID given out by Mojang, and the one an offline mode server gives him
+	const NOTCH_UUID: &str = "069a79f4-44e9-4726-a5be-fca90e38aaf5";
+	const NOTCH_OFFLINE_UUID: &str = "b50ad385-829d-3141-a216-7e7d7539ba7f";
+	const NIL_UUID: &str = "00000000-0000-0000-0000-000000000000";
+
+	// Legacy ping response of a vanilla server, nothing in it matches a fingerprint
+	const VANILLA: &str = "§1\x00767\x001.21.4\x00A Minecraft Server\x000\x0020";
+
+	// Packs a buffer the same way Forge does, 15 bits per character after the length
+	fn encode_optimized(bytes: &[u8]) -> String {
+		let mut characters = vec![
+			(bytes.len() & 0x7FFF) as u16,
+			((bytes.len() >> 15) & 0x7FFF) as u16,
+		];
+		let mut buffer: u32 = 0;
+		let mut bits_in_buffer = 0;
+
+		for byte in bytes {
+			buffer |= (*byte as u32) << bits_in_buffer;
+			bits_in_buffer += 8;
+
+			if bits_in_buffer >= 15 {
+				characters.push((buffer & 0x7FFF) as u16);
+				buffer >>= 15;
+				bits_in_buffer -= 15;
 			}
-			Value::Object(object) => {
-				for (key, value) in object {
-					match key.as_str() {
-						"obfuscated" => {
-							if let Some(b) = value.as_bool() && b {
-								output.push_str("§k")
-							}
-						},
-						"bold" => {
-							if let Some(b) = value.as_bool() && b {
-								output.push_str("§l")
-							}
-						},
-						"strikethrough" => {
-							if let Some(b) = value.as_bool() && b {
-								output.push_str("§m")
-							}
-						},
-						"underline" => {
-							if let Some(b) = value.as_bool() && b {
-								output.push_str("§n")
-							}
-						},
-						"italic" => {
-							if let Some(b) = value.as_bool() && b {
-								output.push_str("§o")
-							}
-						},
-						"color" => {
-							if let Some(c) = value.as_str() {
-								let color = MinecraftColorCodes::from(c);
-								output.push_str(format!("§{}", color.get_code()).as_str())
-							}
-						},
-						_ => (),
-					}
-				}
+		}
 
-				// MiniMOTD can put the "extra" field before the text field, this causes some servers
-				// using it to format incorrectly unless we specifically add the text AFTER
-				// all other format codes but BEFORE the extra field
-				if object.contains_key("text") {
-					if let Some(text) = object.get("text") {
-						if let Some(text) = text.as_str() {
-							output.push_str(text);
-						}
-					}
-				}
+		if bits_in_buffer > 0 {
+			characters.push((buffer & 0x7FFF) as u16);
+		}
 
-				if object.contains_key("extra") {
-					if let Some(extra) = object.get("extra") {
-						output.push_str(&self.build_formatted_description(extra));
-					}
-				}
+		String::from_utf16(&characters).unwrap()
+	}
+
+	fn write_string(buffer: &mut Vec<u8>, string: &str) {
+		(string.len() as i32).encode(buffer);
+		buffer.extend_from_slice(string.as_bytes());
+	}
+
+	fn optimized_buffer() -> Vec<u8> {
+		let mut buffer = vec![1];
+		buffer.extend_from_slice(&2_u16.to_be_bytes());
+
+		// A mod with one channel
+		(1_i32 << 1).encode(&mut buffer);
+		write_string(&mut buffer, "minecraft");
+		write_string(&mut buffer, "1.20.1");
+		write_string(&mut buffer, "register");
+		write_string(&mut buffer, "FML3");
+		buffer.push(1);
+
+		// A server only mod, without a version
+		(1_i32 << 1 | 1).encode(&mut buffer);
+		write_string(&mut buffer, "spark");
+		write_string(&mut buffer, "main");
+		write_string(&mut buffer, "1");
+		buffer.push(0);
+
+		// A channel that doesn't belong to a mod
+		1_i32.encode(&mut buffer);
+		write_string(&mut buffer, "forge:tier_sorting");
+		write_string(&mut buffer, "1.0");
+		buffer.push(0);
+
+		buffer
+	}
+
+	// A vanilla server that only matches the given fingerprint
+	fn server_matching(fingerprint: &Fingerprint) -> Server {
+		let mut server = Server::from_legacy(VANILLA).unwrap();
+
+		match fingerprint {
+			Fingerprint::Version(name) => {
+				server.version.name = format!("{} 1.21.4", name.to_uppercase())
+			}
+			Fingerprint::VersionContains(name) => {
+				server.version.name = format!("1.21.4 ({})", name.to_uppercase())
+			}
+			Fingerprint::Description(text) => {
+				server.description_raw = Some(Value::String(format!("§a{}", text.to_uppercase())))
+			}
+			Fingerprint::Mod(id) => {
+				server.forge_data = Some(ForgeData {
+					mods: vec![Mod {
+						id: id.to_string(),
+						version: "1.0".to_string(),
+					}],
+					channels: vec![],
+					truncated: false,
+				})
+			}
+			Fingerprint::Plugin(name) => {
+				server.query = Some(QueryResponse {
+					description: None,
+					game_type: None,
+					version: None,
+					map: None,
+					online_players: None,
+					max_players: None,
+					software: None,
+					plugins: vec![Plugin {
+						name: format!("{}-Spigot", name.to_uppercase()),
+						version: Some("2.6.0".to_string()),
+					}],
+					players: vec![],
+				})
+			}
+			Fingerprint::Modded => server.modded = Some(true),
+			Fingerprint::Forge => {
+				server.forge_data = Some(ForgeData {
+					mods: vec![],
+					channels: vec![],
+					truncated: false,
+				})
 			}
-			_ => {}
 		}
 
-		output
+		server
+	}
+
+	#[test]
+	fn detects_each_fingerprint() {
+		for (index, (fingerprint, server_type)) in FINGERPRINTS.iter().enumerate() {
+			let server = server_matching(fingerprint);
+			assert_eq!(
+				server.get_type(),
+				*server_type,
+				"fingerprint {index} for {server_type:?}"
+			);
+		}
+	}
+
+	#[test]
+	fn detects_vanilla() {
+		let server = Server::from_legacy(VANILLA).unwrap();
+		assert_eq!(server.get_type(), ServerType::Java);
+	}
+
+	#[test]
+	fn parses_legacy_response() {
+		let server =
+			Server::from_legacy("§1\x0074\x001.6.2\x00A §cred§r server\x003\x0020").unwrap();
+
+		assert_eq!(server.version.name, "1.6.2");
+		assert_eq!(server.version.protocol, 74);
+		assert_eq!(
+			server.description_raw,
+			Some(Value::String("A §cred§r server".to_string()))
+		);
+		assert_eq!(server.players.online, 3);
+		assert_eq!(server.players.max, 20);
+	}
+
+	#[test]
+	fn parses_beta_legacy_response() {
+		// Only the last two section signs separate fields, the rest belong to the description
+		let server = Server::from_legacy("A §cred§r server§3§20").unwrap();
+
+		assert_eq!(server.version.name, LEGACY_VERSION_NAME);
+		assert_eq!(server.version.protocol, -1);
+		assert_eq!(
+			server.description_raw,
+			Some(Value::String("A §cred§r server".to_string()))
+		);
+		assert_eq!(server.players.online, 3);
+		assert_eq!(server.players.max, 20);
+	}
+
+	#[test]
+	fn rejects_malformed_legacy_response() {
+		assert!(Server::from_legacy("§1\x0074\x001.6.2\x00A server\x003").is_none());
+		assert!(Server::from_legacy("§1\x00new\x001.6.2\x00A server\x003\x0020").is_none());
+		assert!(Server::from_legacy("A server§3").is_none());
+		assert!(Server::from_legacy("A server§three§20").is_none());
+	}
+
+	fn player(name: &str, id: &str) -> Player {
+		Player {
+			id: id.to_string(),
+			name: name.to_string(),
+		}
+	}
+
+	fn players(online: i32, sample: Option<Vec<Player>>) -> Players {
+		Players {
+			max: 20,
+			online,
+			sample,
+		}
+	}
+
+	#[test]
+	fn classifies_sample_entries() {
+		let cases = [
+			(player("Notch", NOTCH_UUID), PlayerKind::OnlineMode),
+			(player("Notch", NOTCH_OFFLINE_UUID), PlayerKind::OfflineMode),
+			(
+				player(".Steve", "00000000-0000-0000-0009-01f2c2a1e1d5"),
+				PlayerKind::Bedrock,
+			),
+			(
+				player(ANONYMOUS_PLAYER_NAME, NIL_UUID),
+				PlayerKind::Anonymous,
+			),
+			// Only the anonymous placeholder may use the nil UUID
+			(player("Notch", NIL_UUID), PlayerKind::Text),
+			// Formatted lines are text whatever UUID they were given
+			(player("§6Notch", NOTCH_UUID), PlayerKind::Text),
+			(player("§aWelcome!", NIL_UUID), PlayerKind::Text),
+			(player("Notch", "not a uuid"), PlayerKind::Text),
+			// v1 UUIDs aren't given out to players
+			(
+				player("Notch", "c232ab00-9414-11ec-b3c8-9f6bdeced846"),
+				PlayerKind::Text,
+			),
+		];
+
+		for (player, kind) in cases {
+			assert_eq!(player.kind(), kind, "{player:?}");
+			assert_eq!(
+				player.is_real(),
+				matches!(
+					kind,
+					PlayerKind::OnlineMode | PlayerKind::OfflineMode | PlayerKind::Bedrock
+				),
+				"{player:?}"
+			);
+		}
+	}
+
+	#[test]
+	fn keeps_text_lines_as_sample_text() {
+		let with_text = players(
+			2,
+			Some(vec![
+				player("§6Welcome to the server!", NIL_UUID),
+				player("Notch", NOTCH_UUID),
+				player("Join our Discord", NIL_UUID),
+				player(ANONYMOUS_PLAYER_NAME, NIL_UUID),
+			]),
+		);
+
+		assert_eq!(
+			with_text.sample_text().as_deref(),
+			Some("§6Welcome to the server!\nJoin our Discord")
+		);
+		assert_eq!(players(2, None).sample_text(), None);
+	}
+
+	#[test]
+	fn detects_hidden_players() {
+		// Nobody online, so there is nothing to hide
+		assert_eq!(players(0, None).hides_players(), None);
+		assert_eq!(players(0, Some(vec![])).hides_players(), None);
+
+		assert_eq!(players(3, None).hides_players(), Some(true));
+		assert_eq!(
+			players(3, Some(vec![player("§cPlayers are hidden", NIL_UUID)])).hides_players(),
+			Some(true)
+		);
+		assert_eq!(
+			players(3, Some(vec![player(ANONYMOUS_PLAYER_NAME, NIL_UUID)])).hides_players(),
+			Some(true)
+		);
+		assert_eq!(
+			players(3, Some(vec![player("Notch", NOTCH_UUID)])).hides_players(),
+			Some(false)
+		);
+	}
+
+	#[test]
+	fn makes_offline_uuids_like_vanilla() {
+		assert_eq!(
+			Player::offline_uuid("Notch").to_string(),
+			NOTCH_OFFLINE_UUID
+		);
+		assert_eq!(
+			Player::offline_uuid("jeb_").to_string(),
+			"a762f560-4fce-3236-812a-b80efff0b62b"
+		);
+		assert_eq!(Player::offline_uuid("Notch").get_version_num(), 3);
+	}
+
+	#[test]
+	fn infers_auth_mode_from_sample() {
+		let online = player("Notch", NOTCH_UUID);
+		let offline = player("Notch", NOTCH_OFFLINE_UUID);
+		let bedrock = player(".Steve", "00000000-0000-0000-0009-01f2c2a1e1d5");
+
+		assert_eq!(
+			players(1, Some(vec![online.clone()])).auth_mode(),
+			Some(AuthMode::Online)
+		);
+		assert_eq!(
+			players(1, Some(vec![offline.clone()])).auth_mode(),
+			Some(AuthMode::Offline)
+		);
+		assert_eq!(
+			players(2, Some(vec![online.clone(), offline])).auth_mode(),
+			Some(AuthMode::Mixed)
+		);
+
+		// Bedrock players, text lines and the anonymous placeholder say nothing about it
+		let sample = vec![
+			bedrock.clone(),
+			player("§aWelcome!", NIL_UUID),
+			player(ANONYMOUS_PLAYER_NAME, NIL_UUID),
+		];
+		assert_eq!(players(3, Some(sample)).auth_mode(), None);
+		assert_eq!(
+			players(2, Some(vec![online, bedrock])).auth_mode(),
+			Some(AuthMode::Online)
+		);
+		assert_eq!(players(0, None).auth_mode(), None);
+	}
+
+	#[test]
+	fn v3_uuid_not_made_from_the_name_is_not_offline_mode() {
+		// Notch's offline UUID given to another player, e.g. by a proxy
+		let sample = vec![player("jeb_", NOTCH_OFFLINE_UUID)];
+		assert_eq!(players(1, Some(sample)).auth_mode(), None);
+	}
+
+	#[test]
+	fn login_probe_wins_over_sample() {
+		let mut server = Server::from_legacy(VANILLA).unwrap();
+		server.players = players(1, Some(vec![player("Notch", NOTCH_OFFLINE_UUID)]));
+		assert_eq!(server.auth_mode(), Some(AuthMode::Offline));
+
+		server.login = Some(LoginProbe {
+			status: LoginStatus::OnlineMode,
+			disconnect_reason: None,
+		});
+		assert_eq!(server.auth_mode(), Some(AuthMode::Online));
+
+		// A probe that didn't get in can't tell, so the sample is used
+		server.login = Some(LoginProbe {
+			status: LoginStatus::Whitelisted,
+			disconnect_reason: Some("You are not white-listed on this server!".to_string()),
+		});
+		assert_eq!(server.auth_mode(), Some(AuthMode::Offline));
+	}
+
+	#[test]
+	fn hybrids_win_over_forge() {
+		let mut server = server_matching(&Fingerprint::Forge);
+		server.version.name = "Mohist 1.20.1".to_string();
+
+		assert_eq!(server.get_type(), ServerType::Mohist);
+	}
+
+	#[test]
+	fn fabric_mods_win_over_forge() {
+		let mut server = server_matching(&Fingerprint::Mod("fabric-api"));
+		server.version.name = "Paper 1.20.1".to_string();
+
+		assert_eq!(server.get_type(), ServerType::Fabric);
+	}
+
+	#[test]
+	fn proxies_win_over_backends() {
+		let mut server = server_matching(&Fingerprint::Description("a velocity server"));
+		server.version.name = "Paper 1.21.4".to_string();
+
+		assert_eq!(server.get_type(), ServerType::Velocity);
+	}
+
+	#[test]
+	fn decodes_optimized_buffer() {
+		let bytes = optimized_buffer();
+
+		for length in [0, 1, 2, 14, 15, 16, bytes.len()] {
+			let encoded = encode_optimized(&bytes[..length]);
+			assert_eq!(
+				decode_optimized(&encoded).as_deref(),
+				Some(&bytes[..length])
+			);
+		}
+	}
+
+	#[test]
+	fn rejects_short_optimized_buffer() {
+		let mut encoded: Vec<u16> = encode_optimized(&optimized_buffer())
+			.encode_utf16()
+			.collect();
+		encoded.truncate(encoded.len() - 2);
+
+		assert_eq!(
+			decode_optimized(&String::from_utf16(&encoded).unwrap()),
+			None
+		);
+		assert_eq!(decode_optimized(""), None);
+	}
+
+	#[test]
+	fn deserializes_forge_data_from_d() {
+		let json = serde_json::json!({
+			"d": encode_optimized(&optimized_buffer()),
+			"channels": [],
+			"mods": [],
+			"fmlNetworkVersion": 3,
+		});
+
+		let forge_data: ForgeData = serde_json::from_value(json).unwrap();
+
+		assert!(forge_data.truncated);
+		assert_eq!(
+			forge_data.mods,
+			vec![
+				Mod {
+					id: "minecraft".to_string(),
+					version: "1.20.1".to_string(),
+				},
+				Mod {
+					id: "spark".to_string(),
+					version: IGNORE_SERVER_ONLY.to_string(),
+				},
+			]
+		);
+		assert_eq!(
+			forge_data.channels,
+			vec![
+				Channel {
+					name: "minecraft:register".to_string(),
+					version: "FML3".to_string(),
+					required: true,
+				},
+				Channel {
+					name: "spark:main".to_string(),
+					version: "1".to_string(),
+					required: false,
+				},
+				Channel {
+					name: "forge:tier_sorting".to_string(),
+					version: "1.0".to_string(),
+					required: false,
+				},
+			]
+		);
+	}
+
+	#[test]
+	fn deserializes_plain_forge_data() {
+		let json = serde_json::json!({
+			"modList": [{ "modid": "forge", "version": "14.23.5.2860" }],
+		});
+
+		let forge_data: ForgeData = serde_json::from_value(json).unwrap();
+
+		assert!(!forge_data.truncated);
+		assert!(forge_data.channels.is_empty());
+		assert_eq!(forge_data.mods[0].id, "forge");
+		assert_eq!(forge_data.mods[0].version, "14.23.5.2860");
 	}
 }
@@ -1,27 +1,44 @@
-use crate::config::Config;
+use crate::bedrock::PingableBedrockServer;
+use crate::config::{Config, DiscoveryEngine, RescanSchedule};
 use crate::database::Database;
-use crate::protocol::PingableServer;
+use crate::discovery::{self, Targets};
+use crate::exclusions::Exclusions;
+use crate::limits::{self, Limits, Timeouts};
+use crate::notifications::Event;
+use crate::protocol::{LoginProbe, LoginStatus, PingableServer};
+use crate::query::QueryClient;
 use crate::response::Server;
+use crate::utils::RunError;
 use futures_util::StreamExt;
 use indicatif::{ProgressBar, ProgressStyle};
 use sqlx::{Pool, Postgres, Row};
 use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
+use std::future::Future;
 use std::net::{IpAddr, Ipv4Addr, SocketAddrV4};
 use std::str::FromStr;
 use std::sync::Arc;
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant, SystemTime};
 use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::net::TcpStream;
 use tokio::process::Command;
 use tokio::sync::Semaphore;
+use tokio::time::MissedTickBehavior;
 use tracing::{debug, error, info, warn};
 
-pub static PERMITS: Semaphore = Semaphore::const_new(1000);
-pub const TIMEOUT_SECS: Duration = Duration::from_secs(3);
+// Servers claimed at once by the adaptive rescanner
+const RESCAN_BATCH: i64 = 1000;
+// How long the adaptive rescanner waits before checking again when no servers are due
+const IDLE_DELAY: Duration = Duration::from_secs(10);
+// The adaptive rescanner never finishes a scan, so exclusions are loaded again this often instead
+const EXCLUSIONS_RELOAD: Duration = Duration::from_secs(5 * 60);
+// Written to the temporary directory before every masscan scan
+const MASSCAN_EXCLUDE_FILE: &str = "serverseeker-masscan-exclude.txt";
 
 #[derive(Debug, Default)]
 pub struct ScanBuilder {
 	config: Config,
+	config_file: Option<String>,
 	mode: Mode,
 	pool: Option<Pool<Postgres>>,
 }
@@ -32,6 +49,12 @@ impl ScanBuilder {
 		self
 	}
 
+	/// Limits in the config file are applied again whenever the process receives a SIGHUP
+	pub fn config_file(mut self, config_file: String) -> ScanBuilder {
+		self.config_file = Some(config_file);
+		self
+	}
+
 	pub fn pool(mut self, pool: Option<Pool<Postgres>>) -> ScanBuilder {
 		self.pool = pool;
 		self
@@ -43,18 +66,29 @@ impl ScanBuilder {
 	}
 
 	pub fn build(self) -> Scanner {
+		let database = match self.pool {
+			Some(pool) => Database::new(pool)
+				.track_players(&self.config.player_tracking)
+				.notifications(&self.config.notifications)
+				.history(&self.config.history)
+				.pruning(&self.config.pruning)
+				.rescan(&self.config.rescan),
+			None => {
+				error!("Failed to connect to database!");
+				std::process::exit(1);
+			}
+		};
+
+		let limits = Arc::new(Limits::new(&self.config.scanner));
+		if let Some(config_file) = self.config_file {
+			tokio::spawn(limits::reload_on_hangup(limits.clone(), config_file));
+		}
+
 		Scanner {
-			config: self.config,
+			config: Arc::new(self.config),
 			mode: self.mode,
-			database: {
-				match self.pool {
-					Some(pool) => Database::new(pool),
-					None => {
-						error!("Failed to connect to database!");
-						std::process::exit(1);
-					}
-				}
-			},
+			database,
+			limits,
 		}
 	}
 }
@@ -70,9 +104,10 @@ pub enum Mode {
 
 #[derive(Debug)]
 pub struct Scanner {
-	pub config: Config,
+	pub config: Arc<Config>,
 	pub mode: Mode,
 	pub database: Database,
+	pub limits: Arc<Limits>,
 }
 
 impl Scanner {
@@ -88,8 +123,14 @@ impl Scanner {
 		}
 
 		match self.mode {
-			Mode::Discovery => self.masscan().await,
-			Mode::Rescanner => self.rescan().await,
+			Mode::Discovery => match self.config.discovery.engine {
+				DiscoveryEngine::Masscan => self.masscan().await,
+				DiscoveryEngine::Native => self.native_scan().await,
+			},
+			Mode::Rescanner => match self.config.rescan.schedule {
+				RescanSchedule::Full => self.rescan().await,
+				RescanSchedule::Adaptive => self.adaptive_rescan().await,
+			},
 			Mode::RangeScanner => self.range_scan().await,
 			Mode::GeoUpdate => self.geo_update().await,
 		}
@@ -105,9 +146,11 @@ impl Scanner {
 
 			let ports = self.config.scanner.port_range_start..=self.config.scanner.port_range_end;
 			let (tx, mut rx) = tokio::sync::mpsc::channel::<SocketAddrV4>(10);
+			let exclusions = self.exclusions().await;
+			let java_exclusions = exclusions.clone();
 
 			let mut stream = sqlx::query(
-				"SELECT (address - '0.0.0.0'::inet) AS address FROM servers ORDER BY last_seen ASC",
+				"SELECT (address - '0.0.0.0'::inet) AS address FROM servers WHERE archived_at IS NULL ORDER BY last_seen ASC",
 			)
 			.fetch(&self.database.pool);
 
@@ -125,7 +168,12 @@ impl Scanner {
 					// NOTE: clone is needed because RangeInclusive<T> doesn't implement copy
 					// This should be optimized away anyway
 					for port in ports.clone() {
-						match tx.send(SocketAddrV4::new(address, port)).await {
+						let socket = SocketAddrV4::new(address, port);
+						if java_exclusions.skips(socket) {
+							continue;
+						}
+
+						match tx.send(socket).await {
 							Ok(_) => {}
 							Err(e) => debug!("send channel has been closed! {e}"),
 						}
@@ -149,24 +197,33 @@ impl Scanner {
 				ProgressBar::new((total_servers * self.config.scanner.total_ports() as i64) as u64)
 					.with_style(style);
 
-			// Consume values from the receiver
-			while let Some(socket) = rx.recv().await {
-				let permit = PERMITS.acquire().await;
-
+			let mut dispatcher = self.limits.dispatcher(|socket, permit| {
 				let pool = self.database.clone();
+				let config = self.config.clone();
+				let timeouts = self.limits.timeouts();
 				let bar = bar.clone();
 
 				tokio::spawn(async move {
 					// Move permit to future so it blocks the task as well
 					let _permit = permit;
 
-					task_wrapper(socket, pool).await;
+					task_wrapper(socket, pool, config, timeouts, true).await;
 					bar.inc(1);
 				});
+			});
+
+			// Consume values from the receiver
+			while let Some(socket) = rx.recv().await {
+				dispatcher.push(socket).await;
 			}
 
-			// Sleep for 10 seconds to ensure that all tasks finish
-			tokio::time::sleep(Duration::from_secs(10)).await;
+			dispatcher.drain().await;
+
+			// Bedrock servers aren't in the servers table, they are pinged on their own port
+			self.rescan_bedrock(&exclusions).await;
+
+			// Wait for all tasks to complete
+			self.limits.wait_for_tasks().await;
 			bar.finish_and_clear();
 
 			let end_time = match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
@@ -175,6 +232,7 @@ impl Scanner {
 			};
 
 			info!("Scan completed in {} seconds", end_time - start_time);
+			self.scan_finished("rescan", end_time - start_time).await;
 
 			// Quit if only one scan is requested in config
 			if !self.config.scanner.repeat {
@@ -193,6 +251,113 @@ impl Scanner {
 		}
 	}
 
+	/// Rescans each server when it's due instead of the whole table at once, busy servers
+	/// are due often and offline ones less and less. Runs until nothing is due if repeat is off
+	async fn adaptive_rescan(&self) {
+		let mut exclusions = self.exclusions().await;
+		let mut exclusions_loaded = Instant::now();
+		let mut bedrock_rescanned: Option<Instant> = None;
+		let mut rescanned = 0u64;
+
+		let mut dispatcher = self.limits.dispatcher(|socket, permit| {
+			let database = self.database.clone();
+			let config = self.config.clone();
+			let timeouts = self.limits.timeouts();
+
+			tokio::spawn(async move {
+				let _permit = permit;
+				task_wrapper(socket, database, config, timeouts, true).await;
+			});
+		});
+
+		loop {
+			let now = match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+				Ok(n) => n.as_secs() as i32,
+				Err(_) => panic!("system time before unix epoch!"),
+			};
+
+			if exclusions_loaded.elapsed() >= EXCLUSIONS_RELOAD {
+				exclusions = self.exclusions().await;
+				exclusions_loaded = Instant::now();
+			}
+
+			// Bedrock servers have no schedule of their own, all of them are rescanned every min_interval
+			let min_interval = Duration::from_secs(self.config.rescan.min_interval);
+			if !matches!(bedrock_rescanned, Some(last) if last.elapsed() < min_interval) {
+				self.rescan_bedrock(&exclusions).await;
+				bedrock_rescanned = Some(Instant::now());
+			}
+
+			let due = match self.database.claim_due_servers(now, RESCAN_BATCH, &exclusions).await {
+				Ok(due) => due,
+				Err(e) => {
+					error!("Failed to get servers that are due for a rescan! {e}");
+					tokio::time::sleep(IDLE_DELAY).await;
+					continue;
+				}
+			};
+
+			if due.is_empty() {
+				if rescanned > 0 {
+					info!("Rescanned {rescanned} servers, no more are due right now");
+					rescanned = 0;
+				}
+
+				// Quit if only one scan is requested in config
+				if !self.config.scanner.repeat {
+					dispatcher.finish().await;
+					info!("Exiting");
+					std::process::exit(0);
+				}
+
+				// Nothing new comes in to start the ones set aside for busy subnets
+				dispatcher.drain().await;
+				tokio::time::sleep(IDLE_DELAY).await;
+				continue;
+			}
+
+			for socket in due {
+				dispatcher.push(socket).await;
+				rescanned += 1;
+			}
+		}
+	}
+
+	/// Pings every Bedrock Edition server already in the database again
+	async fn rescan_bedrock(&self, exclusions: &Exclusions) {
+		let mut stream = sqlx::query(
+			"SELECT (address - '0.0.0.0'::inet) AS address, port FROM bedrock_servers ORDER BY last_seen ASC",
+		)
+		.fetch(&self.database.pool);
+
+		let mut dispatcher = self.limits.dispatcher(|socket, permit| {
+			let database = self.database.clone();
+			let timeouts = self.limits.timeouts();
+
+			tokio::spawn(async move {
+				let _permit = permit;
+				bedrock_task_wrapper(socket, database, timeouts).await;
+			});
+		});
+
+		while let Some(Ok(row)) = stream.next().await {
+			let socket = match (row.try_get::<i64, _>("address"), row.try_get::<i32, _>("port")) {
+				(Ok(address), Ok(port)) => {
+					SocketAddrV4::new(Ipv4Addr::from_bits(address as u32), port as u16)
+				}
+				_ => continue,
+			};
+
+			if exclusions.skips_bedrock(socket) {
+				continue;
+			}
+
+			dispatcher.push(socket).await;
+		}
+
+		dispatcher.drain().await;
+	}
+
 	/// Scan IP ranges around existing servers to find new servers
 	async fn range_scan(&self) {
 		loop {
@@ -202,8 +367,9 @@ impl Scanner {
 			};
 
 			// Get unique IP addresses from existing servers (using same query pattern as rescan method)
+			// Suspected honeypots are skipped, their neighbours are likely to be more of the same
 			let mut stream = sqlx::query(
-				"SELECT (address - '0.0.0.0'::inet) AS address FROM servers ORDER BY last_seen ASC",
+				"SELECT (address - '0.0.0.0'::inet) AS address FROM servers WHERE suspected_honeypot IS NULL AND archived_at IS NULL ORDER BY last_seen ASC",
 			)
 			.fetch(&self.database.pool);
 
@@ -236,6 +402,7 @@ impl Scanner {
 			// Generate ranges around each base IP
 			let (tx, mut rx) = tokio::sync::mpsc::channel::<SocketAddrV4>(1000);
 			let ports = self.config.scanner.port_range_start..=self.config.scanner.port_range_end;
+			let exclusions = self.exclusions().await;
 
 			tokio::spawn(async move {
 				for base_ip in base_ips {
@@ -250,6 +417,10 @@ impl Scanner {
 						// Scan all configured ports for this IP
 						for port in ports.clone() {
 							let socket = SocketAddrV4::new(target_ip, port);
+							if exclusions.skips(socket) {
+								continue;
+							}
+
 							if tx.send(socket).await.is_err() {
 								return; // Receiver closed
 							}
@@ -266,18 +437,23 @@ impl Scanner {
 
 			let mut scanned = 0u64;
 
-			// Process the generated ranges
-			while let Some(socket) = rx.recv().await {
-				let permit = PERMITS.acquire().await.expect("semaphore closed");
+			// Targets come one /24 at a time, so a busy subnet's are set aside while the next ones start
+			let mut dispatcher = self.limits.dispatcher(|socket, permit| {
 				let database = self.database.clone();
+				let config = self.config.clone();
+				let timeouts = self.limits.timeouts();
 				let pb_clone = pb.clone();
 
 				tokio::spawn(async move {
 					let _permit = permit;
-					task_wrapper(socket, database).await;
+					task_wrapper(socket, database, config, timeouts, false).await;
 					pb_clone.set_message(format!("Scanned: {}", socket));
 				});
+			});
 
+			// Process the generated ranges
+			while let Some(socket) = rx.recv().await {
+				dispatcher.push(socket).await;
 				scanned += 1;
 				
 				// Break if channel is closed (no more IPs to scan)
@@ -287,9 +463,7 @@ impl Scanner {
 			}
 
 			// Wait for all tasks to complete
-			while PERMITS.available_permits() < 1000 {
-				tokio::time::sleep(Duration::from_millis(100)).await;
-			}
+			dispatcher.finish().await;
 
 			pb.finish_with_message(format!("Range scan completed! Scanned {} addresses", scanned));
 
@@ -299,6 +473,7 @@ impl Scanner {
 			};
 
 			info!("Range scan completed in {} seconds", end_time - start_time);
+			self.scan_finished("range scan", end_time - start_time).await;
 
 			if !self.config.scanner.repeat {
 				info!("Exiting");
@@ -319,12 +494,45 @@ impl Scanner {
 	/// Starts an instance of masscan to find new servers
 	async fn masscan(&self) {
 		loop {
+			let start_time = match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+				Ok(n) => n.as_secs(),
+				Err(_) => panic!("system time before unix epoch!"),
+			};
+
+			// masscan is told about excluded networks as well, so it never sends them any packets
+			let exclusions = self.exclusions().await;
+			let exclude_file = std::env::temp_dir().join(MASSCAN_EXCLUDE_FILE);
+			let mut args = vec![
+				String::from("masscan"),
+				String::from("-c"),
+				self.config.masscan.config_file.clone(),
+			];
+
+			if !exclusions.is_empty() {
+				if let Err(e) = exclusions.write_masscan_file(&exclude_file) {
+					error!(
+						"Failed to write exclusions for masscan to {}! {e}",
+						exclude_file.display()
+					);
+					std::process::exit(1);
+				}
+
+				args.push(String::from("--excludefile"));
+				args.push(exclude_file.display().to_string());
+			}
+
 			// Spawn masscan
-			let mut command = Command::new("sudo")
-				.args(["masscan", "-c", &self.config.masscan.config_file])
+			let mut command = match Command::new("sudo")
+				.args(&args)
 				.stdout(std::process::Stdio::piped())
 				.spawn()
-				.expect("error while executing masscan");
+			{
+				Ok(command) => command,
+				Err(e) => {
+					error!("Failed to run masscan, set engine = \"native\" under [discovery] to scan without it! {e}");
+					std::process::exit(1);
+				}
+			};
 
 			// Verify stdout is valid
 			let stdout = match command.stdout.take() {
@@ -337,18 +545,40 @@ impl Scanner {
 
 			let mut reader = BufReader::new(stdout).lines();
 
+			// Found servers are pinged within the same limits as every other scan
+			let mut java = self.limits.dispatcher(|socket, permit| {
+				let pool = self.database.clone();
+				let config = self.config.clone();
+				let timeouts = self.limits.timeouts();
+
+				tokio::spawn(async move {
+					let _permit = permit;
+					task_wrapper(socket, pool, config, timeouts, false).await;
+				});
+			});
+
+			// Bedrock Edition servers are the only ones listening on UDP
+			let mut bedrock = self.limits.dispatcher(|socket, permit| {
+				let pool = self.database.clone();
+				let timeouts = self.limits.timeouts();
+
+				tokio::spawn(async move {
+					let _permit = permit;
+					bedrock_task_wrapper(socket, pool, timeouts).await;
+				});
+			});
+
 			// Iterate over the lines of output from masscan
 			while let Ok(Some(line)) = reader.next_line().await {
 				let mut line = line.split_whitespace();
 
-				let port = match line
-					.nth(3)
-					// Split on port/tcp
-					.and_then(|p| p.split('/').nth(0))
-					// Parse as u16
-					.and_then(|s| s.parse::<u16>().ok())
-				{
-					Some(port) => port,
+				// Split on port/protocol
+				let (port, protocol) = match line.nth(3).and_then(|p| p.split_once('/')) {
+					// Parse port as u16
+					Some((port, protocol)) => match port.parse::<u16>() {
+						Ok(port) => (port, protocol.to_string()),
+						Err(_) => continue,
+					},
 					None => continue,
 				};
 
@@ -358,16 +588,32 @@ impl Scanner {
 					None => continue,
 				};
 
-				let pool = self.database.clone();
+				let socket = SocketAddrV4::new(address, port);
+				let skipped = match protocol.as_str() {
+					"udp" => exclusions.skips_bedrock(socket),
+					_ => exclusions.skips(socket),
+				};
+				if skipped {
+					continue;
+				}
 
 				// Spawn a pinging task for each server found
-				tokio::spawn(async move {
-					let socket = SocketAddrV4::new(address, port);
-
-					task_wrapper(socket, pool).await;
-				});
+				match protocol.as_str() {
+					"udp" => bedrock.push(socket).await,
+					_ => java.push(socket).await,
+				}
 			}
 
+			java.finish().await;
+			bedrock.finish().await;
+
+			let end_time = match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+				Ok(d) => d.as_secs(),
+				Err(_) => panic!("system time before unix epoch!"),
+			};
+
+			self.scan_finished("discovery", end_time - start_time).await;
+
 			// Quit if only one scan is requested in config
 			if !self.config.scanner.repeat {
 				info!("Exiting");
@@ -384,18 +630,264 @@ impl Scanner {
 			}
 		}
 	}
+
+	/// Finds new servers by connecting to every address and port in the configured ranges
+	async fn native_scan(&self) {
+		let discovery = &self.config.discovery;
+
+		let mut ranges = Vec::new();
+		for range in &discovery.ranges {
+			match discovery::parse_range(range) {
+				Some(range) => ranges.push(range),
+				None => {
+					error!("Invalid range in config file: {range}");
+					std::process::exit(1);
+				}
+			}
+		}
+
+		loop {
+			let start_time = match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+				Ok(n) => n.as_secs(),
+				Err(_) => panic!("system time before unix epoch!"),
+			};
+
+			// A new random order every scan
+			let targets = Targets::new(ranges.clone(), discovery.ports.clone());
+			if targets.is_empty() {
+				error!("No ranges or ports to scan, add them under [discovery] in the config file!");
+				std::process::exit(1);
+			}
+
+			info!("Scanning {} addresses and ports", targets.len());
+
+			let style = ProgressStyle::with_template(
+				"[{elapsed_precise}] [{bar:40.white/blue}] {human_pos}/{human_len} {msg}",
+			)
+			.expect("failed to create progress bar style")
+			.progress_chars("=>-");
+
+			let bar = ProgressBar::new(targets.len()).with_style(style);
+			let exclusions = self.exclusions().await;
+			let database = self.database.clone();
+			let config = self.config.clone();
+
+			connect_scan(
+				&targets,
+				&exclusions,
+				&self.limits,
+				discovery.rate,
+				&bar,
+				move |socket, timeouts| {
+					task_wrapper(socket, database.clone(), config.clone(), timeouts, false)
+				},
+			)
+			.await;
+
+			bar.finish_and_clear();
+
+			let end_time = match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+				Ok(d) => d.as_secs(),
+				Err(_) => panic!("system time before unix epoch!"),
+			};
+
+			info!("Discovery scan completed in {} seconds", end_time - start_time);
+			self.scan_finished("discovery", end_time - start_time).await;
+
+			if !self.config.scanner.repeat {
+				info!("Exiting");
+				std::process::exit(0);
+			}
+
+			// Wait scan delay before starting a new scan
+			if self.config.scanner.scan_delay > 0 {
+				info!(
+					"Waiting {} seconds before starting another scan...",
+					self.config.scanner.scan_delay
+				);
+				tokio::time::sleep(Duration::from_secs(self.config.scanner.scan_delay)).await;
+			}
+		}
+	}
+
+	/// Loads the exclusions again, so networks and servers added while running are left out from the next scan
+	async fn exclusions(&self) -> Arc<Exclusions> {
+		match Exclusions::load(&self.config.exclusions, &self.database.pool).await {
+			Ok(exclusions) => Arc::new(exclusions),
+			Err(e) => {
+				error!("Failed to load exclusions! {e}");
+				std::process::exit(1);
+			}
+		}
+	}
+
+	/// Notifies webhooks that a scan has finished
+	async fn scan_finished(&self, mode: &str, duration_secs: u64) {
+		if !self.database.notifier.is_enabled() {
+			return;
+		}
+
+		match self.database.count_servers().await {
+			Ok(total_servers) => self.database.notifier.send(Event::ScanFinished {
+				mode: String::from(mode),
+				duration_secs,
+				total_servers,
+			}),
+			Err(e) => warn!("Failed to count servers for scan notification: {e}"),
+		}
+	}
+}
+
+/// Connects to every target, starting no more than `rate` connections a second, and calls `found`
+/// with each one that accepted the connection. Returns once every task has finished
+async fn connect_scan<F, Fut>(
+	targets: &Targets,
+	exclusions: &Exclusions,
+	limits: &Arc<Limits>,
+	rate: u64,
+	bar: &ProgressBar,
+	found: F,
+) where
+	F: Fn(SocketAddrV4, Timeouts) -> Fut + Send + Sync + 'static,
+	Fut: Future<Output = ()> + Send + 'static,
+{
+	// Connections are started in batches, 100 times a second at most
+	let rate = rate.max(1);
+	let batch = (rate / 100).max(1);
+	let period = Duration::from_secs_f64(batch as f64 / rate as f64);
+
+	// Ticks missed while waiting for a permit would otherwise all fire at once afterwards
+	let mut interval = tokio::time::interval(period);
+	interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+	let found = Arc::new(found);
+	let mut targets = targets.iter().peekable();
+
+	let mut dispatcher = limits.dispatcher(|socket, permit| {
+		let timeouts = limits.timeouts();
+		let found = found.clone();
+		let bar = bar.clone();
+
+		tokio::spawn(async move {
+			let _permit = permit;
+
+			// Only open ports are pinged, so nothing is written for closed ones
+			let connect = tokio::time::timeout(timeouts.connect, TcpStream::connect(socket));
+			if let Ok(Ok(stream)) = connect.await {
+				drop(stream);
+				found(socket, timeouts).await;
+			}
+
+			bar.inc(1);
+		});
+	});
+
+	while targets.peek().is_some() {
+		interval.tick().await;
+
+		for socket in targets.by_ref().take(batch as usize) {
+			if exclusions.skips(socket) {
+				bar.inc(1);
+				continue;
+			}
+
+			dispatcher.push(socket).await;
+		}
+	}
+
+	// Wait for all tasks to complete
+	dispatcher.finish().await;
 }
 
+/// Pings a server and stores the result. Failures are only recorded for servers that came from
+/// the servers table, anything else would be an update of a row that doesn't exist
 #[inline(always)]
-async fn task_wrapper(socket: SocketAddrV4, pool: Database) {
-	let server = PingableServer::new(socket);
+async fn task_wrapper(
+	socket: SocketAddrV4,
+	pool: Database,
+	config: Arc<Config>,
+	timeouts: Timeouts,
+	known: bool,
+) {
+	let server = PingableServer::new(socket)
+		.hostname(config.scanner.hostnames.get(socket.ip()).cloned())
+		.protocol_version(config.scanner.protocol_version)
+		.measure_latency(config.scanner.measure_latency)
+		.connect_timeout(timeouts.connect)
+		.read_timeout(timeouts.read);
+
+	let response = match config.scanner.proper_ping {
+		true => server.proper_ping().await,
+		false => server.simple_ping().await,
+	};
+
+	let response = match response {
+		Ok(response) => match serde_json::from_str::<Server>(&response.json) {
+			Ok(mut status) => {
+				status.latency_ms = response.latency.map(|l| l.as_millis() as i32);
+
+				// Log in with the servers own protocol version, otherwise we just get told we're outdated
+				if config.scanner.login_probe {
+					let probe = server
+						.login_probe(status.version.protocol, &config.scanner.login_username)
+						.await;
+
+					// A failed probe is stored as well, so it can be told apart from one that was never sent
+					status.login = Some(probe.unwrap_or_else(|e| {
+						debug!("[{}] Login probe failed: {e}", socket.ip());
+						LoginProbe {
+							status: LoginStatus::Unknown,
+							disconnect_reason: Some(format!(
+								"Login probe failed: {}",
+								e.failure_reason()
+							)),
+						}
+					}));
+				}
+
+				Ok(status)
+			}
+			Err(e) => Err(RunError::from(e)),
+		},
+		// Servers older than 1.7 don't understand the modern handshake,
+		// fall back to the legacy ping before giving up on them
+		Err(RunError::MalformedResponse | RunError::VarIntTooLong | RunError::VarIntIncomplete) => {
+			server.legacy_ping().await
+		}
+		Err(e) => Err(e),
+	};
+
+	match response {
+		Ok(mut server) => {
+			if config.scanner.query {
+				server.query = QueryClient::new(socket)
+					.timeout(timeouts.read)
+					.query()
+					.await
+					.ok();
+			}
 
-	if let Ok(response) = server.simple_ping().await {
-		if let Ok(server) = serde_json::from_str::<Server>(&response) {
 			if let Err(e) = pool.update_server(server, socket).await {
 				debug!("Error updating server in database! {e}");
 			}
 		}
+		Err(e) if known => {
+			if let Err(e) = pool.record_failure(socket, &e).await {
+				debug!("Error recording failed ping in database! {e}");
+			}
+		}
+		Err(_) => {}
+	}
+}
+
+#[inline(always)]
+async fn bedrock_task_wrapper(socket: SocketAddrV4, pool: Database, timeouts: Timeouts) {
+	let server = PingableBedrockServer::new(socket).timeout(timeouts.read);
+
+	if let Ok(server) = server.ping().await {
+		if let Err(e) = pool.update_bedrock_server(server, socket).await {
+			debug!("Error updating bedrock server in database! {e}");
+		}
 	}
 }
 
@@ -602,3 +1094,60 @@ impl Scanner {
 		}
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::config::Exclusions as ExclusionsConfig;
+	use crate::exclusions;
+	use std::sync::Mutex;
+	use tokio::net::TcpListener;
+
+	// Finds every open target with the native engine, in the order they were found
+	async fn scan(targets: &Targets, default_exclusions: bool) -> Vec<SocketAddrV4> {
+		let config = ExclusionsConfig {
+			default_exclusions,
+			file: None,
+		};
+		let exclusions = Exclusions::from(exclusions::configured_networks(&config).unwrap());
+		let limits = Arc::new(Limits::new(&Config::default().scanner));
+		let found = Arc::new(Mutex::new(Vec::new()));
+
+		let sockets = found.clone();
+		connect_scan(
+			targets,
+			&exclusions,
+			&limits,
+			1000,
+			&ProgressBar::hidden(),
+			move |socket, _| {
+				sockets.lock().unwrap().push(socket);
+				async {}
+			},
+		)
+		.await;
+
+		let found = found.lock().unwrap();
+		found.to_vec()
+	}
+
+	#[tokio::test]
+	async fn finds_loopback_listeners() {
+		let listener = TcpListener::bind("127.0.0.3:0").await.unwrap();
+		let open = listener.local_addr().unwrap().port();
+		// Nothing listens on a port that was just closed
+		let closed = TcpListener::bind("127.0.0.3:0").await.unwrap().local_addr();
+		let closed = closed.unwrap().port();
+
+		let ranges = vec![discovery::parse_range("127.0.0.2/31").unwrap()];
+		let targets = Targets::new(ranges, vec![open, closed]);
+
+		assert_eq!(
+			scan(&targets, false).await,
+			vec![SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 3), open)]
+		);
+
+		// Loopback is excluded by default
+		assert_eq!(scan(&targets, true).await, vec![]);
+	}
+}
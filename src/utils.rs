@@ -16,6 +16,20 @@ pub enum RunError {
 	ServerOptOut,
 	#[error("Error while updating server in database")]
 	DatabaseError(#[from] sqlx::Error),
+	#[error("VarInt is longer than the maximum length")]
+	VarIntTooLong,
+	#[error("VarInt ended before its last byte")]
+	VarIntIncomplete,
+}
+
+impl RunError {
+	/// Stored as a servers last_failure_reason when pinging it fails
+	pub fn failure_reason(&self) -> String {
+		match self {
+			RunError::IOError(e) => format!("{self}: {}", e.kind()),
+			_ => self.to_string(),
+		}
+	}
 }
 
 impl From<RunError> for usize {
@@ -30,83 +44,8 @@ impl From<RunError> for usize {
 			TimedOut(_) => 4,
 			ServerOptOut => 5,
 			DatabaseError(_) => 6,
-		}
-	}
-}
-
-pub enum MinecraftColorCodes {
-	Black,
-	DarkBlue,
-	DarkGreen,
-	DarkAqua,
-	DarkRed,
-	DarkPurple,
-	Gold,
-	Gray,
-	DarkGray,
-	Blue,
-	Green,
-	Aqua,
-	Red,
-	LightPurple,
-	Yellow,
-	White,
-	Reset,
-	UnknownValue,
-}
-
-impl From<&str> for MinecraftColorCodes {
-	fn from(s: &str) -> Self {
-		use MinecraftColorCodes::*;
-
-		match s {
-			"black" => Black,
-			"dark_blue" => DarkBlue,
-			"dark_green" => DarkGreen,
-			"dark_aqua" => DarkAqua,
-			"dark_red" => DarkRed,
-			"dark_purple" | "purple" => DarkPurple,
-			"gold" => Gold,
-			"gray" | "grey" => Gray,
-			"dark_gray" | "dark_grey" => DarkGray,
-			"blue" => Blue,
-			"green" => Green,
-			"aqua" => Aqua,
-			"red" => Red,
-			"pink" | "light_purple" => LightPurple,
-			"yellow" => Yellow,
-			"white" => White,
-			"reset" => Reset,
-			_ => UnknownValue,
-		}
-	}
-}
-
-impl MinecraftColorCodes {
-	pub fn get_code(&self) -> char {
-		use MinecraftColorCodes::*;
-
-		match self {
-			Black => '0',
-			DarkBlue => '1',
-			DarkGreen => '2',
-			DarkAqua => '3',
-			DarkRed => '4',
-			DarkPurple => '5',
-			Gold => '6',
-			Gray => '7',
-			DarkGray => '8',
-			Blue => '9',
-			Green => 'a',
-			Aqua => 'b',
-			Red => 'c',
-			LightPurple => 'd',
-			Yellow => 'e',
-			White => 'f',
-			Reset => 'r',
-			// TODO: Currently its only servers that respond with hex values as colors that don't match
-			// Maybe theres a way with color averaging to fix this?
-			UnknownValue => 'r',
+			VarIntTooLong => 7,
+			VarIntIncomplete => 8,
 		}
 	}
 }
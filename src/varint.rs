@@ -0,0 +1,220 @@
+use crate::utils::RunError;
+use std::io::ErrorKind;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Variable length integers as used by the Minecraft protocol. Each byte holds 7 bits of the
+/// value, the most significant bit is set when more bytes follow
+pub trait VarInt: Sized {
+	/// The most bytes a value of this type can be encoded as
+	const MAX_BYTES: usize;
+
+	/// Appends the encoded value to the end of the buffer
+	fn encode(self, buffer: &mut Vec<u8>);
+
+	/// Returns the decoded value and how many bytes were read
+	fn decode(bytes: &[u8]) -> Result<(Self, usize), RunError>;
+}
+
+macro_rules! impl_varint {
+	($signed:ty, $unsigned:ty, $max_bytes:expr) => {
+		impl VarInt for $signed {
+			const MAX_BYTES: usize = $max_bytes;
+
+			fn encode(self, buffer: &mut Vec<u8>) {
+				// Negative numbers are encoded as their two's complement
+				let mut value = self as $unsigned;
+
+				loop {
+					if value & !0x7F == 0 {
+						buffer.push(value as u8);
+						return;
+					}
+
+					buffer.push((value & 0x7F) as u8 | 0x80);
+					value >>= 7;
+				}
+			}
+
+			fn decode(bytes: &[u8]) -> Result<(Self, usize), RunError> {
+				let mut value: $unsigned = 0;
+
+				for (i, b) in bytes.iter().enumerate() {
+					if i == Self::MAX_BYTES {
+						return Err(RunError::VarIntTooLong);
+					}
+
+					// Bits that don't fit in the last byte are discarded, same as vanilla
+					value |= ((b & 0x7F) as $unsigned) << (i * 7);
+
+					// Most significant bit isn't set, this is the end of the varint
+					if b & 0x80 == 0 {
+						return Ok((value as $signed, i + 1));
+					}
+				}
+
+				// Ran out of bytes before the end of the varint
+				match bytes.len() {
+					len if len >= Self::MAX_BYTES => Err(RunError::VarIntTooLong),
+					_ => Err(RunError::VarIntIncomplete),
+				}
+			}
+		}
+	};
+}
+
+impl_varint!(i32, u32, 5);
+impl_varint!(i64, u64, 10);
+
+/// Reads a varint directly from a stream one byte at a time
+pub async fn read_varint<R: AsyncRead + Unpin>(stream: &mut R) -> Result<i32, RunError> {
+	let mut bytes = Vec::with_capacity(i32::MAX_BYTES);
+
+	loop {
+		let byte = match stream.read_u8().await {
+			Ok(byte) => byte,
+			// Stream ended part way through the varint
+			Err(e) if e.kind() == ErrorKind::UnexpectedEof && !bytes.is_empty() => {
+				return Err(RunError::VarIntIncomplete)
+			}
+			Err(e) => return Err(e.into()),
+		};
+
+		bytes.push(byte);
+
+		if byte & 0x80 == 0 || bytes.len() == i32::MAX_BYTES {
+			return i32::decode(&bytes).map(|(value, _)| value);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn encoded<T: VarInt>(value: T) -> Vec<u8> {
+		let mut buffer = Vec::new();
+		value.encode(&mut buffer);
+		buffer
+	}
+
+	#[test]
+	fn round_trips_i32() {
+		let cases = [
+			(0, 1),
+			(1, 1),
+			(127, 1),
+			(128, 2),
+			(16383, 2),
+			(16384, 3),
+			// Largest values that fit in 4 bytes and smallest that need all 5
+			((1 << 28) - 1, 4),
+			(1 << 28, 5),
+			(i32::MAX, 5),
+			// Negative numbers always take every byte
+			(-1, 5),
+			(i32::MIN, 5),
+		];
+
+		for (value, length) in cases {
+			let bytes = encoded(value);
+			assert_eq!(bytes.len(), length, "{value}");
+			assert_eq!(i32::decode(&bytes).unwrap(), (value, length), "{value}");
+		}
+	}
+
+	#[test]
+	fn round_trips_i64() {
+		let cases = [
+			(0, 1),
+			(-1, 10),
+			(i64::MIN, 10),
+			(i64::MAX, 9),
+			((1 << 56) - 1, 8),
+			(1 << 56, 9),
+			(i32::MAX as i64, 5),
+		];
+
+		for (value, length) in cases {
+			let bytes = encoded(value);
+			assert_eq!(bytes.len(), length, "{value}");
+			assert_eq!(i64::decode(&bytes).unwrap(), (value, length), "{value}");
+		}
+	}
+
+	#[test]
+	fn encodes_like_vanilla() {
+		assert_eq!(encoded(255), [0xFF, 0x01]);
+		assert_eq!(encoded(25565), [0xDD, 0xC7, 0x01]);
+		assert_eq!(encoded(-1), [0xFF, 0xFF, 0xFF, 0xFF, 0x0F]);
+		assert_eq!(encoded(i32::MIN), [0x80, 0x80, 0x80, 0x80, 0x08]);
+	}
+
+	#[test]
+	fn decode_stops_at_last_byte() {
+		assert_eq!(i32::decode(&[0xDD, 0xC7, 0x01, 0xFF]).unwrap(), (25565, 3));
+	}
+
+	#[test]
+	fn rejects_too_long() {
+		// A 6th byte for an i32, the 5th still has the continuation bit set
+		let bytes = [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01];
+		assert!(matches!(i32::decode(&bytes), Err(RunError::VarIntTooLong)));
+		assert!(matches!(
+			i32::decode(&bytes[..5]),
+			Err(RunError::VarIntTooLong)
+		));
+
+		let bytes = [0xFF; 11];
+		assert!(matches!(i64::decode(&bytes), Err(RunError::VarIntTooLong)));
+	}
+
+	#[test]
+	fn rejects_incomplete() {
+		let bytes = encoded(25565);
+		assert!(matches!(
+			i32::decode(&bytes[..2]),
+			Err(RunError::VarIntIncomplete)
+		));
+		assert!(matches!(i32::decode(&[]), Err(RunError::VarIntIncomplete)));
+		assert!(matches!(
+			i64::decode(&encoded(-1_i64)[..9]),
+			Err(RunError::VarIntIncomplete)
+		));
+	}
+
+	#[tokio::test]
+	async fn reads_from_stream() {
+		let mut bytes = encoded(25565);
+		bytes.push(0x2A);
+		let mut stream = bytes.as_slice();
+
+		assert_eq!(read_varint(&mut stream).await.unwrap(), 25565);
+		assert_eq!(read_varint(&mut stream).await.unwrap(), 42);
+	}
+
+	#[tokio::test]
+	async fn read_rejects_too_long() {
+		let mut stream: &[u8] = &[0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01];
+		assert!(matches!(
+			read_varint(&mut stream).await,
+			Err(RunError::VarIntTooLong)
+		));
+	}
+
+	#[tokio::test]
+	async fn read_on_eof() {
+		// Nothing was sent at all
+		let mut stream: &[u8] = &[];
+		match read_varint(&mut stream).await {
+			Err(RunError::IOError(e)) => assert_eq!(e.kind(), ErrorKind::UnexpectedEof),
+			result => panic!("expected end of stream, got {result:?}"),
+		}
+
+		// Stream ended part way through
+		let mut stream: &[u8] = &[0xDD, 0xC7];
+		assert!(matches!(
+			read_varint(&mut stream).await,
+			Err(RunError::VarIntIncomplete)
+		));
+	}
+}
@@ -0,0 +1,211 @@
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MinecraftColorCodes {
+	Black,
+	DarkBlue,
+	DarkGreen,
+	DarkAqua,
+	DarkRed,
+	DarkPurple,
+	Gold,
+	Gray,
+	DarkGray,
+	Blue,
+	Green,
+	Aqua,
+	Red,
+	LightPurple,
+	Yellow,
+	White,
+	Reset,
+	UnknownValue,
+}
+
+impl From<&str> for MinecraftColorCodes {
+	fn from(s: &str) -> Self {
+		use MinecraftColorCodes::*;
+
+		match s {
+			"black" => Black,
+			"dark_blue" => DarkBlue,
+			"dark_green" => DarkGreen,
+			"dark_aqua" => DarkAqua,
+			"dark_red" => DarkRed,
+			"dark_purple" | "purple" => DarkPurple,
+			"gold" => Gold,
+			"gray" | "grey" => Gray,
+			"dark_gray" | "dark_grey" => DarkGray,
+			"blue" => Blue,
+			"green" => Green,
+			"aqua" => Aqua,
+			"red" => Red,
+			"pink" | "light_purple" => LightPurple,
+			"yellow" => Yellow,
+			"white" => White,
+			"reset" => Reset,
+			_ => UnknownValue,
+		}
+	}
+}
+
+impl MinecraftColorCodes {
+	// Every color in the order of their codes
+	const COLORS: [MinecraftColorCodes; 16] = {
+		use MinecraftColorCodes::*;
+		[
+			Black,
+			DarkBlue,
+			DarkGreen,
+			DarkAqua,
+			DarkRed,
+			DarkPurple,
+			Gold,
+			Gray,
+			DarkGray,
+			Blue,
+			Green,
+			Aqua,
+			Red,
+			LightPurple,
+			Yellow,
+			White,
+		]
+	};
+
+	pub fn get_code(&self) -> char {
+		use MinecraftColorCodes::*;
+
+		match self {
+			Black => '0',
+			DarkBlue => '1',
+			DarkGreen => '2',
+			DarkAqua => '3',
+			DarkRed => '4',
+			DarkPurple => '5',
+			Gold => '6',
+			Gray => '7',
+			DarkGray => '8',
+			Blue => '9',
+			Green => 'a',
+			Aqua => 'b',
+			Red => 'c',
+			LightPurple => 'd',
+			Yellow => 'e',
+			White => 'f',
+			Reset => 'r',
+			// Hex colors are handled by Color, anything else is invalid
+			UnknownValue => 'r',
+		}
+	}
+
+	/// Color for a § code, "§r" isn't a color so it's not included
+	pub fn from_code(code: char) -> Option<MinecraftColorCodes> {
+		let index = code.to_digit(16)?;
+		Some(Self::COLORS[index as usize])
+	}
+
+	/// Name of the color, as used in JSON components
+	pub fn name(&self) -> &'static str {
+		use MinecraftColorCodes::*;
+
+		match self {
+			Black => "black",
+			DarkBlue => "dark_blue",
+			DarkGreen => "dark_green",
+			DarkAqua => "dark_aqua",
+			DarkRed => "dark_red",
+			DarkPurple => "dark_purple",
+			Gold => "gold",
+			Gray => "gray",
+			DarkGray => "dark_gray",
+			Blue => "blue",
+			Green => "green",
+			Aqua => "aqua",
+			Red => "red",
+			LightPurple => "light_purple",
+			Yellow => "yellow",
+			White => "white",
+			Reset => "reset",
+			UnknownValue => "unknown",
+		}
+	}
+
+	/// The color the vanilla client shows text in
+	pub fn rgb(&self) -> (u8, u8, u8) {
+		use MinecraftColorCodes::*;
+
+		match self {
+			Black => (0x00, 0x00, 0x00),
+			DarkBlue => (0x00, 0x00, 0xAA),
+			DarkGreen => (0x00, 0xAA, 0x00),
+			DarkAqua => (0x00, 0xAA, 0xAA),
+			DarkRed => (0xAA, 0x00, 0x00),
+			DarkPurple => (0xAA, 0x00, 0xAA),
+			Gold => (0xFF, 0xAA, 0x00),
+			Gray => (0xAA, 0xAA, 0xAA),
+			DarkGray => (0x55, 0x55, 0x55),
+			Blue => (0x55, 0x55, 0xFF),
+			Green => (0x55, 0xFF, 0x55),
+			Aqua => (0x55, 0xFF, 0xFF),
+			Red => (0xFF, 0x55, 0x55),
+			LightPurple => (0xFF, 0x55, 0xFF),
+			Yellow => (0xFF, 0xFF, 0x55),
+			White | Reset | UnknownValue => (0xFF, 0xFF, 0xFF),
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Color {
+	Named(MinecraftColorCodes),
+	Hex(u8, u8, u8),
+}
+
+impl Color {
+	/// Parses either a color name or a hex color like "#ff8800"
+	pub fn parse(color: &str) -> Option<Color> {
+		if let Some(hex) = color.strip_prefix('#') {
+			return Color::from_hex(hex);
+		}
+
+		match MinecraftColorCodes::from(color) {
+			MinecraftColorCodes::UnknownValue | MinecraftColorCodes::Reset => None,
+			color => Some(Color::Named(color)),
+		}
+	}
+
+	/// Parses six hex digits without the leading "#"
+	pub fn from_hex(hex: &str) -> Option<Color> {
+		if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+			return None;
+		}
+
+		let value = u32::from_str_radix(hex, 16).ok()?;
+		Some(Color::Hex(
+			(value >> 16) as u8,
+			(value >> 8) as u8,
+			value as u8,
+		))
+	}
+
+	pub fn rgb(&self) -> (u8, u8, u8) {
+		match self {
+			Color::Named(color) => color.rgb(),
+			Color::Hex(r, g, b) => (*r, *g, *b),
+		}
+	}
+
+	/// Hex colors are written as §x followed by each hex digit, e.g. §x§f§f§8§8§0§0
+	pub fn legacy_code(&self) -> String {
+		match self {
+			Color::Named(color) => format!("§{}", color.get_code()),
+			Color::Hex(r, g, b) => format!("{:02x}{:02x}{:02x}", r, g, b).chars().fold(
+				String::from("§x"),
+				|mut output, c| {
+					output.push('§');
+					output.push(c);
+					output
+				},
+			),
+		}
+	}
+}
@@ -0,0 +1,540 @@
+//! Minecraft chat components, parsed from either JSON or § formatted strings and
+//! rendered as plain text, § codes, ANSI escape codes or HTML
+
+mod color;
+mod render;
+
+pub use color::{Color, MinecraftColorCodes};
+use serde_json::Value;
+
+// Translatable components can nest, this stops a malicious server recursing forever
+const MAX_DEPTH: usize = 32;
+// Translation arguments can be used any number of times, so a few hundred bytes of nested
+// arguments can render as gigabytes. Rendering stops once this many bytes and components are visited
+const MAX_RENDERED: usize = 64 * 1024;
+
+/// A chat component from a servers description, with its children already parsed
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Component {
+	pub content: Content,
+	pub style: Style,
+	pub extra: Vec<Component>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Content {
+	// Can contain § codes, they are applied on top of the components style
+	Text(String),
+	// Rendered with the fallback if there is one, otherwise the key itself, as we
+	// don't have the clients language files
+	Translate {
+		key: String,
+		fallback: Option<String>,
+		with: Vec<Component>,
+	},
+	// Name of the key, e.g. "key.jump". The client would show whatever it's bound to
+	Keybind(String),
+	// Only rendered if the server has already resolved the value
+	Score {
+		name: String,
+		objective: String,
+		value: Option<String>,
+	},
+	Selector(String),
+}
+
+impl Default for Content {
+	fn default() -> Self {
+		Content::Text(String::new())
+	}
+}
+
+/// Styles are optional so unset ones can be inherited from the parent component
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Style {
+	pub color: Option<Color>,
+	pub bold: Option<bool>,
+	pub italic: Option<bool>,
+	pub underlined: Option<bool>,
+	pub strikethrough: Option<bool>,
+	pub obfuscated: Option<bool>,
+	// Set by the "reset" color, stops the parents color and formatting being inherited
+	pub reset: bool,
+}
+
+impl Style {
+	fn parse(object: &serde_json::Map<String, Value>) -> Style {
+		// Some servers send booleans as strings
+		let flag = |key: &str| match object.get(key) {
+			Some(Value::Bool(b)) => Some(*b),
+			Some(Value::String(s)) => s.parse().ok(),
+			_ => None,
+		};
+
+		let color = object.get("color").and_then(Value::as_str);
+
+		Style {
+			color: color.and_then(Color::parse),
+			bold: flag("bold"),
+			italic: flag("italic"),
+			// "underline" isn't valid but some servers use it
+			underlined: flag("underlined").or_else(|| flag("underline")),
+			strikethrough: flag("strikethrough"),
+			obfuscated: flag("obfuscated"),
+			reset: color == Some("reset"),
+		}
+	}
+
+	/// Fills in anything this style doesn't set from the parents style
+	pub fn inherit(&self, parent: &Style) -> Style {
+		if self.reset {
+			return *self;
+		}
+
+		Style {
+			color: self.color.or(parent.color),
+			bold: self.bold.or(parent.bold),
+			italic: self.italic.or(parent.italic),
+			underlined: self.underlined.or(parent.underlined),
+			strikethrough: self.strikethrough.or(parent.strikethrough),
+			obfuscated: self.obfuscated.or(parent.obfuscated),
+			reset: false,
+		}
+	}
+
+	// Only keeps formatting that's turned on, so two styles that look the same are equal
+	fn normalize(&self) -> Style {
+		let on = |flag: Option<bool>| flag.filter(|f| *f);
+
+		Style {
+			color: self.color,
+			bold: on(self.bold),
+			italic: on(self.italic),
+			underlined: on(self.underlined),
+			strikethrough: on(self.strikethrough),
+			obfuscated: on(self.obfuscated),
+			reset: false,
+		}
+	}
+
+	/// Formatting codes that are turned on, in the order vanilla writes them
+	pub fn formats(&self) -> impl Iterator<Item = char> {
+		[
+			(self.obfuscated, 'k'),
+			(self.bold, 'l'),
+			(self.strikethrough, 'm'),
+			(self.underlined, 'n'),
+			(self.italic, 'o'),
+		]
+		.into_iter()
+		.filter(|(enabled, _)| *enabled == Some(true))
+		.map(|(_, code)| code)
+	}
+
+	// Applies a § code the same way the client does. A color clears formatting
+	// and a reset goes back to the style the text started with
+	fn apply_code(&self, code: char, base: &Style) -> Style {
+		match code {
+			'k' => Style {
+				obfuscated: Some(true),
+				..*self
+			},
+			'l' => Style {
+				bold: Some(true),
+				..*self
+			},
+			'm' => Style {
+				strikethrough: Some(true),
+				..*self
+			},
+			'n' => Style {
+				underlined: Some(true),
+				..*self
+			},
+			'o' => Style {
+				italic: Some(true),
+				..*self
+			},
+			'r' => *base,
+			code => match MinecraftColorCodes::from_code(code) {
+				Some(color) => Style {
+					color: Some(Color::Named(color)),
+					..Default::default()
+				},
+				None => *self,
+			},
+		}
+	}
+}
+
+impl From<&Value> for Component {
+	fn from(value: &Value) -> Self {
+		Component::parse(value, 0)
+	}
+}
+
+impl Component {
+	/// Parses a § formatted string, e.g. from a legacy ping
+	pub fn from_legacy(text: &str) -> Component {
+		Component::text(text)
+	}
+
+	fn parse(value: &Value, depth: usize) -> Component {
+		if depth > MAX_DEPTH {
+			return Component::default();
+		}
+
+		let children = |value: Option<&Value>| match value {
+			Some(Value::Array(array)) => array
+				.iter()
+				.map(|v| Component::parse(v, depth + 1))
+				.collect(),
+			Some(value) => vec![Component::parse(value, depth + 1)],
+			None => Vec::new(),
+		};
+
+		match value {
+			Value::String(s) => Component::text(s),
+			Value::Number(n) => Component::text(&n.to_string()),
+			Value::Bool(b) => Component::text(&b.to_string()),
+			// The first element is the parent of the rest
+			Value::Array(array) => match array.split_first() {
+				Some((first, rest)) => {
+					let mut component = Component::parse(first, depth + 1);
+					component
+						.extra
+						.extend(rest.iter().map(|v| Component::parse(v, depth + 1)));
+					component
+				}
+				None => Component::default(),
+			},
+			Value::Object(object) => {
+				let string = |key: &str| object.get(key).and_then(Value::as_str).map(String::from);
+
+				let content = if let Some(key) = string("translate") {
+					Content::Translate {
+						key,
+						fallback: string("fallback"),
+						with: children(object.get("with")),
+					}
+				} else if let Some(key) = string("keybind") {
+					Content::Keybind(key)
+				} else if let Some(score) = object.get("score").and_then(Value::as_object) {
+					let string =
+						|key: &str| score.get(key).and_then(Value::as_str).map(String::from);
+
+					Content::Score {
+						name: string("name").unwrap_or_default(),
+						objective: string("objective").unwrap_or_default(),
+						value: string("value"),
+					}
+				} else if let Some(selector) = string("selector") {
+					Content::Selector(selector)
+				} else {
+					match object.get("text") {
+						Some(Value::String(s)) => Content::Text(s.clone()),
+						Some(Value::Number(n)) => Content::Text(n.to_string()),
+						Some(Value::Bool(b)) => Content::Text(b.to_string()),
+						_ => Content::default(),
+					}
+				};
+
+				Component {
+					content,
+					style: Style::parse(object),
+					extra: children(object.get("extra")),
+				}
+			}
+			Value::Null => Component::default(),
+		}
+	}
+
+	fn text(text: &str) -> Component {
+		Component {
+			content: Content::Text(text.to_string()),
+			..Default::default()
+		}
+	}
+
+	/// Calls the function with each piece of text and the style it's shown with,
+	/// in the order they are displayed. Pieces can be empty when a § code has no
+	/// text after it, so the code isn't lost when rendering back to § codes
+	pub fn visit(&self, f: &mut impl FnMut(&str, &Style)) {
+		let mut budget = MAX_RENDERED;
+		self.visit_with(&Style::default(), &mut budget, f);
+	}
+
+	// The budget is shared by every component visited, each one costs its own text and one more
+	fn visit_with(&self, parent: &Style, budget: &mut usize, f: &mut impl FnMut(&str, &Style)) {
+		let cost = 1 + match &self.content {
+			Content::Text(text) => text.len(),
+			Content::Translate { key, fallback, .. } => fallback.as_ref().unwrap_or(key).len(),
+			Content::Keybind(key) => key.len(),
+			Content::Score { value, .. } => value.as_ref().map_or(0, String::len),
+			Content::Selector(selector) => selector.len(),
+		};
+
+		if cost > *budget {
+			*budget = 0;
+			return;
+		}
+
+		*budget -= cost;
+		let style = self.style.inherit(parent);
+
+		match &self.content {
+			Content::Text(text) => legacy_runs(text, &style, f),
+			Content::Translate {
+				key,
+				fallback,
+				with,
+			} => {
+				let format = fallback.as_ref().unwrap_or(key);
+				translate(format, with, &style, budget, f);
+			}
+			Content::Keybind(key) => f(key, &style.normalize()),
+			Content::Score { value, .. } => {
+				f(value.as_deref().unwrap_or_default(), &style.normalize())
+			}
+			Content::Selector(selector) => f(selector, &style.normalize()),
+		}
+
+		for child in &self.extra {
+			child.visit_with(&style, budget, f);
+		}
+	}
+}
+
+// Splits text on § codes, calling the function with each piece and its style
+fn legacy_runs(text: &str, style: &Style, f: &mut impl FnMut(&str, &Style)) {
+	let mut current = *style;
+	let mut start = 0;
+	let mut chars = text.char_indices();
+
+	while let Some((index, c)) = chars.next() {
+		if c != '§' {
+			continue;
+		}
+
+		// Text before the code has to be emitted before the style changes
+		if index > start {
+			f(&text[start..index], &current.normalize());
+		}
+
+		let Some((_, code)) = chars.next() else {
+			start = text.len();
+			break;
+		};
+
+		// Hex colors are §x followed by six § codes, one for each digit
+		if code.eq_ignore_ascii_case(&'x') {
+			let rest = chars.as_str();
+			let digits: String = rest.chars().skip(1).step_by(2).take(6).collect();
+			let valid = rest.chars().step_by(2).take(6).all(|c| c == '§');
+
+			if let Some(color) = Color::from_hex(&digits).filter(|_| valid) {
+				current = Style {
+					color: Some(color),
+					..Default::default()
+				};
+				// Every character was checked to be ASCII or §, so skip the 12 characters
+				chars.nth(11);
+			}
+		} else {
+			current = current.apply_code(code.to_ascii_lowercase(), style);
+		}
+
+		start = text.len() - chars.as_str().len();
+		f("", &current.normalize());
+	}
+
+	if start < text.len() || start == 0 {
+		f(&text[start..], &current.normalize());
+	}
+}
+
+// Replaces %s and %1$s with the arguments, "%%" is a literal percent sign.
+// Arguments are rendered with their own styles in place
+fn translate(
+	format: &str,
+	with: &[Component],
+	style: &Style,
+	budget: &mut usize,
+	f: &mut impl FnMut(&str, &Style),
+) {
+	let mut next_argument = 0;
+	let mut literal = String::new();
+	let mut chars = format.chars().peekable();
+
+	while let Some(c) = chars.next() {
+		if c != '%' {
+			literal.push(c);
+			continue;
+		}
+
+		// Either "s", "%" or a position like "1$s"
+		let mut specifier = String::new();
+		while let Some(c) = chars.next_if(|c| c.is_ascii_digit() || *c == '$') {
+			specifier.push(c);
+		}
+
+		match chars.next_if(|c| *c == 's' || *c == '%') {
+			Some('%') if specifier.is_empty() => literal.push('%'),
+			Some('s') => {
+				let index = match specifier.strip_suffix('$') {
+					Some(position) => position
+						.parse::<usize>()
+						.ok()
+						.and_then(|p| p.checked_sub(1)),
+					None if specifier.is_empty() => {
+						next_argument += 1;
+						Some(next_argument - 1)
+					}
+					None => None,
+				};
+
+				legacy_runs(&std::mem::take(&mut literal), style, f);
+
+				if let Some(argument) = index.and_then(|i| with.get(i)) {
+					argument.visit_with(style, budget, f);
+				}
+			}
+			// Not a valid specifier, keep it as written
+			other => {
+				literal.push('%');
+				literal.push_str(&specifier);
+				literal.extend(other);
+			}
+		}
+	}
+
+	legacy_runs(&literal, style, f);
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use serde_json::json;
+
+	#[test]
+	fn translates_arguments() {
+		let component = Component::from(&json!({
+			"translate": "chat.type.text",
+			"fallback": "<%s> %2$s %%",
+			"with": ["Steve", {"text": "hello", "color": "gold"}],
+		}));
+
+		assert_eq!(component.to_plain(), "<Steve> hello %");
+		assert_eq!(component.to_legacy(), "<Steve> §6hello§r %");
+	}
+
+	#[test]
+	fn stops_rendering_repeated_arguments() {
+		// Every level renders the one below it four times
+		let mut description = json!("A".repeat(64));
+		for _ in 0..20 {
+			description = json!({"translate": "%1$s%1$s%1$s%1$s", "with": [description]});
+		}
+
+		let component = Component::from(&description);
+		let plain = component.to_plain();
+
+		assert!(!plain.is_empty());
+		assert!(plain.len() <= MAX_RENDERED);
+		assert!(component.to_html().len() <= MAX_RENDERED);
+	}
+
+	#[test]
+	fn html_escapes_text() {
+		let component = Component::from(&json!({
+			"text": "<script>alert(\"a & 'b'\")</script>",
+			"color": "red",
+		}));
+
+		assert_eq!(
+			component.to_html(),
+			"<span class=\"mc-red\">&lt;script&gt;alert(&quot;a &amp; &#39;b&#39;&quot;)&lt;/script&gt;</span>"
+		);
+	}
+
+	#[test]
+	fn html_hex_colors_are_only_digits() {
+		let component = Component::from(&json!({"text": "a", "color": "#FF8800"}));
+		assert_eq!(
+			component.to_html(),
+			"<span style=\"color: #ff8800\">a</span>"
+		);
+
+		// Anything that isn't six hex digits is dropped instead of ending up in the attribute
+		let component = Component::from(&json!({"text": "a", "color": "#\"><b>"}));
+		assert_eq!(component.to_html(), "a");
+		let component = Component::from_legacy("§x§\"§>§<§0§0§0a");
+		assert!(!component.to_html().contains("<b"));
+		assert!(!component.to_html().contains("\"><"));
+	}
+
+	#[test]
+	fn html_spans_are_balanced() {
+		let component = Component::from(&json!({
+			"text": "a",
+			"color": "gold",
+			"extra": [
+				{"text": "b", "bold": true, "extra": [{"text": "c", "italic": true}]},
+				{"text": "d", "color": "#123456"},
+				{"text": "e", "color": "reset"},
+			],
+		}));
+
+		let html = component.to_html();
+		assert_eq!(
+			html,
+			concat!(
+				"<span class=\"mc-gold\">a</span>",
+				"<span class=\"mc-gold mc-bold\">b</span>",
+				"<span class=\"mc-gold mc-bold mc-italic\">c</span>",
+				"<span style=\"color: #123456\">d</span>",
+				"e",
+			)
+		);
+		assert_eq!(
+			html.matches("<span").count(),
+			html.matches("</span>").count()
+		);
+	}
+
+	#[test]
+	fn legacy_round_trips() {
+		for text in [
+			"plain",
+			"§6gold §lbold§r plain",
+			"§x§f§f§8§8§0§0hex §aand named",
+			"§c§lred bold§r§o italic",
+			"§k§mobfuscated and struck",
+		] {
+			assert_eq!(Component::from_legacy(text).to_legacy(), text, "{text}");
+		}
+	}
+
+	#[test]
+	fn legacy_normalizes_codes() {
+		// Uppercase codes, redundant resets and formatting a color clears again
+		assert_eq!(
+			Component::from_legacy("§r§Aa§lb§6c").to_legacy(),
+			"§aa§lb§6c"
+		);
+		assert_eq!(
+			Component::from_legacy("§X§F§F§8§8§0§0a").to_legacy(),
+			"§x§f§f§8§8§0§0a"
+		);
+		// An invalid hex color is ignored, and unknown codes are hidden like the client does
+		assert_eq!(Component::from_legacy("§x§z§z§z§z§z§za").to_legacy(), "a");
+	}
+
+	#[test]
+	fn budget_is_shared_by_siblings() {
+		let argument = json!({"translate": "%1$s%1$s%1$s%1$s", "with": ["A".repeat(1024)]});
+		let description = json!({"text": "", "extra": vec![argument; 1024]});
+
+		assert!(Component::from(&description).to_plain().len() <= MAX_RENDERED);
+	}
+}
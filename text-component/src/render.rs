@@ -0,0 +1,168 @@
+use crate::{Color, Component, Style};
+
+impl Component {
+	/// Renders the component without any formatting
+	pub fn to_plain(&self) -> String {
+		let mut output = String::new();
+		self.visit(&mut |text, _| output.push_str(text));
+		output
+	}
+
+	/// Renders the component with § codes, only writing codes where the style changes
+	pub fn to_legacy(&self) -> String {
+		let mut output = String::new();
+		let mut current = Style::default();
+
+		self.visit(&mut |text, style| {
+			// Color codes clear formatting, so it has to be written again after one.
+			// This is also the only way to remove formatting
+			if style.color != current.color
+				|| current.formats().any(|f| !style.formats().any(|c| c == f))
+			{
+				match style.color {
+					Some(color) => output.push_str(&color.legacy_code()),
+					None => output.push_str("§r"),
+				}
+
+				current = Style {
+					color: style.color,
+					..Default::default()
+				};
+			}
+
+			for code in style.formats() {
+				if !current.formats().any(|c| c == code) {
+					output.push('§');
+					output.push(code);
+				}
+			}
+
+			current = *style;
+			output.push_str(text);
+		});
+
+		output
+	}
+
+	/// Renders the component with 24 bit ANSI escape codes for terminals
+	pub fn to_ansi(&self) -> String {
+		let mut output = String::new();
+		let mut current = Style::default();
+
+		self.visit(&mut |text, style| {
+			if text.is_empty() {
+				return;
+			}
+
+			if *style != current {
+				if current != Style::default() {
+					output.push_str("\x1b[0m");
+				}
+
+				let mut codes = Vec::new();
+
+				if let Some(color) = style.color {
+					let (r, g, b) = color.rgb();
+					codes.push(format!("38;2;{r};{g};{b}"));
+				}
+
+				// Terminals don't have anything like obfuscated text
+				for code in style.formats() {
+					match code {
+						'l' => codes.push(String::from("1")),
+						'o' => codes.push(String::from("3")),
+						'n' => codes.push(String::from("4")),
+						'm' => codes.push(String::from("9")),
+						_ => (),
+					}
+				}
+
+				if !codes.is_empty() {
+					output.push_str(&format!("\x1b[{}m", codes.join(";")));
+				}
+
+				current = *style;
+			}
+
+			output.push_str(text);
+		});
+
+		if current != Style::default() {
+			output.push_str("\x1b[0m");
+		}
+
+		output
+	}
+
+	/// Renders the component as HTML with each piece of text escaped. Named colors and
+	/// formatting use "mc-" classes, e.g. "mc-gold mc-bold", hex colors are set inline
+	pub fn to_html(&self) -> String {
+		let mut output = String::new();
+		let mut current = Style::default();
+
+		self.visit(&mut |text, style| {
+			if text.is_empty() {
+				return;
+			}
+
+			// Spans are never nested, each one is closed before the style changes
+			if *style != current {
+				if current != Style::default() {
+					output.push_str("</span>");
+				}
+
+				if *style != Style::default() {
+					output.push_str(&open_span(style));
+				}
+
+				current = *style;
+			}
+
+			for c in text.chars() {
+				match c {
+					'&' => output.push_str("&amp;"),
+					'<' => output.push_str("&lt;"),
+					'>' => output.push_str("&gt;"),
+					'"' => output.push_str("&quot;"),
+					'\'' => output.push_str("&#39;"),
+					'\n' => output.push_str("<br>"),
+					c => output.push(c),
+				}
+			}
+		});
+
+		if current != Style::default() {
+			output.push_str("</span>");
+		}
+
+		output
+	}
+}
+
+fn open_span(style: &Style) -> String {
+	let mut classes = Vec::new();
+	let mut css = String::new();
+
+	match style.color {
+		Some(Color::Named(color)) => classes.push(format!("mc-{}", color.name().replace('_', "-"))),
+		Some(Color::Hex(r, g, b)) => css = format!(" style=\"color: #{r:02x}{g:02x}{b:02x}\""),
+		None => (),
+	}
+
+	for code in style.formats() {
+		let class = match code {
+			'k' => "mc-obfuscated",
+			'l' => "mc-bold",
+			'm' => "mc-strikethrough",
+			'n' => "mc-underline",
+			_ => "mc-italic",
+		};
+
+		classes.push(String::from(class));
+	}
+
+	match classes.is_empty() {
+		true => format!("<span{css}>"),
+		false => format!("<span class=\"{}\"{css}>", classes.join(" ")),
+	}
+}
@@ -1,15 +1,14 @@
 use axum::{
     extract::{Path, Query, State},
-    http::StatusCode,
-    response::Json,
+    http::{header, StatusCode},
+    response::{IntoResponse, Json},
     routing::{get, post, put},
     Router,
 };
 use serde::{Deserialize, Serialize};
-use regex::Regex;
-use std::sync::OnceLock;
 use sqlx::{PgPool, Row};
 use std::net::SocketAddr;
+use text_component::Component;
 use tower_http::{cors::CorsLayer, services::ServeDir};
 use tracing::info;
 
@@ -23,9 +22,14 @@ struct Server {
     description_formatted: Option<String>,
     online_players: Option<i32>,
     max_players: Option<i32>,
+    latency_ms: Option<i32>,
+    auth_mode: Option<String>,
+    icon_hash: Option<String>,
     country: Option<String>,
     first_seen: i32,
     last_seen: i32,
+    is_online: bool,
+    last_failure_reason: Option<String>,
     status: String,
     visited_at: Option<chrono::DateTime<chrono::Utc>>,
     notes: Option<String>,
@@ -39,6 +43,10 @@ struct ServerFilters {
     country: Option<String>,
     min_players: Option<i32>,
     max_players: Option<i32>,
+    auth_mode: Option<String>,
+    online: Option<String>,
+    icon_hash: Option<String>,
+    search: Option<String>,
     sort_by: Option<String>,
     sort_order: Option<String>,
     limit: Option<i32>,
@@ -52,6 +60,38 @@ struct VisitUpdate {
     rating: Option<i32>,
 }
 
+#[derive(Debug, Deserialize)]
+struct HistoryRange {
+    // Unix timestamps, everything is returned when not set
+    since: Option<i32>,
+    until: Option<i32>,
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+struct Snapshot {
+    taken_at: i32,
+    online_players: Option<i32>,
+    max_players: Option<i32>,
+    software: Option<String>,
+    version: Option<String>,
+    protocol: Option<i32>,
+    latency_ms: Option<i32>,
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+struct Change {
+    changed_at: i32,
+    field: String,
+    old: Option<String>,
+    new: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct ServerHistory {
+    snapshots: Vec<Snapshot>,
+    changes: Vec<Change>,
+}
+
 #[derive(Debug, Serialize)]
 struct ScanStats {
     total_servers: i64,
@@ -83,7 +123,9 @@ async fn main() -> anyhow::Result<()> {
         .route("/api/servers", get(list_servers))
         .route("/api/servers/:address/:port/visit", post(mark_visited))
         .route("/api/servers/:address/:port/visit", put(update_visit))
+        .route("/api/servers/:address/:port/history", get(get_history))
         .route("/api/stats", get(get_stats))
+        .route("/api/favicons/:file", get(get_favicon))
         .nest_service("/", ServeDir::new("static"))
         .layer(CorsLayer::permissive())
         .with_state(state);
@@ -98,75 +140,6 @@ async fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
-fn minecraft_color_codes_to_html(text: &str) -> String {
-    static COLOR_REGEX: OnceLock<Regex> = OnceLock::new();
-    let regex = COLOR_REGEX.get_or_init(|| {
-        Regex::new(r"§([0-9a-fklmnor])").unwrap()
-    });
-
-    let mut result = String::new();
-    let mut last_end = 0;
-    let mut open_span = false;
-
-    for caps in regex.captures_iter(text) {
-        let full_match = caps.get(0).unwrap();
-        let code = caps.get(1).unwrap().as_str();
-        
-        // Add text before this match
-        result.push_str(&text[last_end..full_match.start()]);
-        
-        // Close previous span if open
-        if open_span {
-            result.push_str("</span>");
-            open_span = false;
-        }
-        
-        // Add new span based on color code
-        match code {
-            "0" => { result.push_str(r#"<span class="mc-black">"#); open_span = true; },
-            "1" => { result.push_str(r#"<span class="mc-dark-blue">"#); open_span = true; },
-            "2" => { result.push_str(r#"<span class="mc-dark-green">"#); open_span = true; },
-            "3" => { result.push_str(r#"<span class="mc-dark-aqua">"#); open_span = true; },
-            "4" => { result.push_str(r#"<span class="mc-dark-red">"#); open_span = true; },
-            "5" => { result.push_str(r#"<span class="mc-dark-purple">"#); open_span = true; },
-            "6" => { result.push_str(r#"<span class="mc-gold">"#); open_span = true; },
-            "7" => { result.push_str(r#"<span class="mc-gray">"#); open_span = true; },
-            "8" => { result.push_str(r#"<span class="mc-dark-gray">"#); open_span = true; },
-            "9" => { result.push_str(r#"<span class="mc-blue">"#); open_span = true; },
-            "a" => { result.push_str(r#"<span class="mc-green">"#); open_span = true; },
-            "b" => { result.push_str(r#"<span class="mc-aqua">"#); open_span = true; },
-            "c" => { result.push_str(r#"<span class="mc-red">"#); open_span = true; },
-            "d" => { result.push_str(r#"<span class="mc-light-purple">"#); open_span = true; },
-            "e" => { result.push_str(r#"<span class="mc-yellow">"#); open_span = true; },
-            "f" => { result.push_str(r#"<span class="mc-white">"#); open_span = true; },
-            "k" => { result.push_str(r#"<span class="mc-obfuscated">"#); open_span = true; },
-            "l" => { result.push_str(r#"<span class="mc-bold">"#); open_span = true; },
-            "m" => { result.push_str(r#"<span class="mc-strikethrough">"#); open_span = true; },
-            "n" => { result.push_str(r#"<span class="mc-underline">"#); open_span = true; },
-            "o" => { result.push_str(r#"<span class="mc-italic">"#); open_span = true; },
-            "r" => { 
-                if open_span {
-                    result.push_str("</span>");
-                    open_span = false;
-                }
-            },
-            _ => {}
-        }
-        
-        last_end = full_match.end();
-    }
-
-    // Add remaining text
-    result.push_str(&text[last_end..]);
-    
-    // Close any remaining open span
-    if open_span {
-        result.push_str("</span>");
-    }
-
-    result
-}
-
 async fn list_servers(
     State(state): State<AppState>,
     Query(filters): Query<ServerFilters>,
@@ -181,12 +154,18 @@ async fn list_servers(
             s.software::text,
             s.version,
             s.protocol,
+            s.description_raw,
             s.description_formatted,
             s.online_players,
             s.max_players,
+            s.latency_ms,
+            s.auth_mode::text,
+            s.icon_hash,
             s.country,
             s.first_seen,
             s.last_seen,
+            s.is_online,
+            s.last_failure_reason,
             COALESCE(v.status::text, 'not_visited') as status,
             v.visited_at,
             v.notes,
@@ -231,13 +210,44 @@ async fn list_servers(
         query.push_str(&format!(" AND s.max_players <= {}", max_players));
     }
 
+    if let Some(auth_mode) = &filters.auth_mode {
+        match auth_mode.as_str() {
+            "online" | "offline" | "mixed" => query.push_str(&format!(" AND s.auth_mode = '{}'", auth_mode)),
+            "unknown" => query.push_str(" AND s.auth_mode IS NULL"),
+            _ => {}
+        }
+    }
+
+    // Archived servers haven't been seen for a long time and are no longer rescanned
+    if let Some(online) = &filters.online {
+        match online.as_str() {
+            "online" => query.push_str(" AND s.is_online AND s.archived_at IS NULL"),
+            "offline" => query.push_str(" AND NOT s.is_online AND s.archived_at IS NULL"),
+            "archived" => query.push_str(" AND s.archived_at IS NOT NULL"),
+            _ => {}
+        }
+    }
+
+    // Hashes are hex encoded SHA-256
+    if let Some(icon_hash) = &filters.icon_hash {
+        if icon_hash.len() == 64 && icon_hash.chars().all(|c| c.is_ascii_hexdigit()) {
+            query.push_str(&format!(" AND s.icon_hash = '{}'", icon_hash));
+        }
+    }
+
+    // Bound as a parameter below, descriptions can contain anything
+    let search = filters.search.as_deref().filter(|s| !s.is_empty()).map(escape_like);
+    if search.is_some() {
+        query.push_str(" AND s.description_plain ILIKE '%' || $1 || '%' ESCAPE '\\'");
+    }
+
     // Handle sorting
     let sort_field = filters.sort_by.as_deref().unwrap_or("last_seen");
     let sort_order = filters.sort_order.as_deref().unwrap_or("desc").to_uppercase();
     
     let valid_sort_fields = vec![
         "last_seen", "first_seen", "online_players", "max_players", 
-        "address", "software", "country", "rating"
+        "address", "software", "country", "rating", "latency_ms"
     ];
     
     if valid_sort_fields.contains(&sort_field) && (sort_order == "ASC" || sort_order == "DESC") {
@@ -245,6 +255,7 @@ async fn list_servers(
             "address" => query.push_str(&format!(" ORDER BY host(s.address) {}", sort_order)),
             "software" => query.push_str(&format!(" ORDER BY s.software::text {}", sort_order)),
             "rating" => query.push_str(&format!(" ORDER BY v.rating {} NULLS LAST", sort_order)),
+            "latency_ms" => query.push_str(&format!(" ORDER BY s.latency_ms {} NULLS LAST", sort_order)),
             _ => query.push_str(&format!(" ORDER BY s.{} {}", sort_field, sort_order)),
         }
     } else {
@@ -253,7 +264,12 @@ async fn list_servers(
     
     query.push_str(&format!(" LIMIT {} OFFSET {}", limit, offset));
 
-    let rows = sqlx::query(&query)
+    let mut rows = sqlx::query(&query);
+    if let Some(search) = search {
+        rows = rows.bind(search);
+    }
+
+    let rows = rows
         .fetch_all(&state.db)
         .await
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
@@ -261,8 +277,13 @@ async fn list_servers(
     let servers: Vec<Server> = rows
         .into_iter()
         .map(|row| {
-            let description: Option<String> = row.get("description_formatted");
-            let processed_description = description.map(|desc| minecraft_color_codes_to_html(&desc));
+            // Rendered from the raw description so nothing is lost to § codes
+            let raw: Option<serde_json::Value> = row.get("description_raw");
+            let formatted: Option<String> = row.get("description_formatted");
+            let processed_description = match raw {
+                Some(raw) => Some(Component::from(&raw).to_html()),
+                None => formatted.map(|desc| Component::from_legacy(&desc).to_html()),
+            };
             
             Server {
                 address: row.get("address"),
@@ -273,9 +294,14 @@ async fn list_servers(
                 description_formatted: processed_description,
                 online_players: row.get("online_players"),
                 max_players: row.get("max_players"),
+                latency_ms: row.get("latency_ms"),
+                auth_mode: row.get("auth_mode"),
+                icon_hash: row.get("icon_hash"),
                 country: row.get("country"),
                 first_seen: row.get("first_seen"),
                 last_seen: row.get("last_seen"),
+                is_online: row.get("is_online"),
+                last_failure_reason: row.get("last_failure_reason"),
                 status: row.get("status"),
                 visited_at: row.get("visited_at"),
                 notes: row.get("notes"),
@@ -287,6 +313,29 @@ async fn list_servers(
     Ok(Json(servers))
 }
 
+async fn get_favicon(
+    State(state): State<AppState>,
+    Path(file): Path<String>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let hash = file.strip_suffix(".png").ok_or(StatusCode::NOT_FOUND)?;
+
+    let png: Vec<u8> = sqlx::query_scalar("SELECT png FROM favicons WHERE hash = $1")
+        .bind(hash)
+        .fetch_optional(&state.db)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    // Favicons are stored by the hash of their contents, so they never change
+    let headers = [
+        (header::CONTENT_TYPE, "image/png".to_string()),
+        (header::CACHE_CONTROL, "public, max-age=31536000, immutable".to_string()),
+        (header::ETAG, format!("\"{}\"", hash)),
+    ];
+
+    Ok((headers, png))
+}
+
 async fn mark_visited(
     State(state): State<AppState>,
     Path((address, port)): Path<(String, String)>,
@@ -349,6 +398,46 @@ async fn update_visit(
     Ok(StatusCode::OK)
 }
 
+async fn get_history(
+    State(state): State<AppState>,
+    Path((address, port)): Path<(String, String)>,
+    Query(range): Query<HistoryRange>,
+) -> Result<Json<ServerHistory>, StatusCode> {
+    let port: i32 = port.parse().map_err(|_| StatusCode::BAD_REQUEST)?;
+    let address: std::net::IpAddr = address.parse().map_err(|_| StatusCode::BAD_REQUEST)?;
+
+    // Oldest first, ready to be charted
+    let snapshots: Vec<Snapshot> = sqlx::query_as(
+        "SELECT taken_at, online_players, max_players, software::text AS software, version, protocol, latency_ms
+         FROM server_snapshots WHERE address = $1 AND port = $2
+         AND ($3::integer IS NULL OR taken_at >= $3) AND ($4::integer IS NULL OR taken_at <= $4)
+         ORDER BY taken_at"
+    )
+    .bind(address)
+    .bind(port)
+    .bind(range.since)
+    .bind(range.until)
+    .fetch_all(&state.db)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let changes: Vec<Change> = sqlx::query_as(
+        "SELECT changed_at, field, old, new
+         FROM server_changes WHERE address = $1 AND port = $2
+         AND ($3::integer IS NULL OR changed_at >= $3) AND ($4::integer IS NULL OR changed_at <= $4)
+         ORDER BY changed_at"
+    )
+    .bind(address)
+    .bind(port)
+    .bind(range.since)
+    .bind(range.until)
+    .fetch_all(&state.db)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(ServerHistory { snapshots, changes }))
+}
+
 async fn get_stats(State(state): State<AppState>) -> Result<Json<ScanStats>, StatusCode> {
     let total_servers: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM servers")
         .fetch_one(&state.db)
@@ -397,3 +486,27 @@ async fn get_stats(State(state): State<AppState>) -> Result<Json<ScanStats>, Sta
     Ok(Json(stats))
 }
 
+
+// Searches match the text as typed, so LIKE wildcards in it are escaped
+fn escape_like(search: &str) -> String {
+    let mut escaped = String::with_capacity(search.len());
+    for c in search.chars() {
+        if matches!(c, '\\' | '%' | '_') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_like_wildcards() {
+        assert_eq!(escape_like("100% PvP_Server"), "100\\% PvP\\_Server");
+        assert_eq!(escape_like("C:\\"), "C:\\\\");
+        assert_eq!(escape_like("plain"), "plain");
+    }
+}